@@ -1,27 +1,25 @@
-// main.rs
+// png_parser.rs
 use std::env;
-use std::io;
 
-mod chunk;
-mod error;
-mod header;
-mod image_type;
-mod png;
-mod raw_data;
-
-use error::PngError;
+use png_parser::{PngError, PngReader};
 
 fn main() -> Result<(), PngError> {
     let args: Vec<String> = env::args().collect();
     if args.len() < 3 {
-        eprintln!("Usage: {} <input.png> <print_size>", args[0]);
+        eprintln!(
+            "Usage: {} <input.png> <print_size> [display_gamma]",
+            args[0]
+        );
         return Ok(());
     }
 
     let file_path = &args[1];
     let print_size = args[2].parse::<usize>().unwrap_or(20);
+    // Gamma correction is applied only when a display gamma is given.
+    let display_gamma = args.get(3).and_then(|arg| arg.parse::<f64>().ok());
 
-    let mut reader = png::PngReader::new(file_path)?;
+    let mut reader = PngReader::new(file_path)?;
+    reader.set_display_gamma(display_gamma)?;
     let raw_png = reader.load_png()?;
     println!("{}", raw_png);
     for chunk in &raw_png.chunks {
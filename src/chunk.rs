@@ -1,34 +1,115 @@
 // chunk.rs
 use std::fmt;
+use std::str::FromStr;
 
-const IHDR: [u8; 4] = [73, 72, 68, 82];
-const IDAT: [u8; 4] = [73, 68, 65, 84];
-const IEND: [u8; 4] = [73, 69, 78, 68];
+use crate::error::PngError;
 
-#[derive(Debug)]
+const IHDR: [u8; 4] = *b"IHDR";
+const PLTE: [u8; 4] = *b"PLTE";
+const IDAT: [u8; 4] = *b"IDAT";
+const IEND: [u8; 4] = *b"IEND";
+const ACTL: [u8; 4] = *b"acTL";
+const BKGD: [u8; 4] = *b"bKGD";
+const CHRM: [u8; 4] = *b"cHRM";
+const CICP: [u8; 4] = *b"cICP";
+const CLLI: [u8; 4] = *b"cLLI";
+const EXIF: [u8; 4] = *b"eXIf";
+const FCTL: [u8; 4] = *b"fcTL";
+const FDAT: [u8; 4] = *b"fdAT";
+const GAMA: [u8; 4] = *b"gAMA";
+const HIST: [u8; 4] = *b"hIST";
+const ICCP: [u8; 4] = *b"iCCP";
+const ITXT: [u8; 4] = *b"iTXt";
+const MDCV: [u8; 4] = *b"mDCV";
+const PHYS: [u8; 4] = *b"pHYs";
+const SBIT: [u8; 4] = *b"sBIT";
+const SPLT: [u8; 4] = *b"sPLT";
+const SRGB: [u8; 4] = *b"sRGB";
+const TEXT: [u8; 4] = *b"tEXt";
+const TIME: [u8; 4] = *b"tIME";
+const TRNS: [u8; 4] = *b"tRNS";
+const ZTXT: [u8; 4] = *b"zTXt";
+const GIFG: [u8; 4] = *b"gIFg";
+const GIFX: [u8; 4] = *b"gIFx";
+const OFFS: [u8; 4] = *b"oFFs";
+const PCAL: [u8; 4] = *b"pCAL";
+const SCAL: [u8; 4] = *b"sCAL";
+const STER: [u8; 4] = *b"sTER";
+
+/// Chunk types registered for PNG, named exactly as they appear in a file.
+/// Any other four-byte type is kept as-is in `Other`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[allow(non_camel_case_types)]
 pub enum ChunkType {
     IHDR,
+    PLTE,
     IDAT,
     IEND,
-    Unknown,
+    acTL,
+    bKGD,
+    cHRM,
+    cICP,
+    cLLI,
+    eXIf,
+    fcTL,
+    fdAT,
+    gAMA,
+    hIST,
+    iCCP,
+    iTXt,
+    mDCV,
+    pHYs,
+    sBIT,
+    sPLT,
+    sRGB,
+    tEXt,
+    tIME,
+    tRNS,
+    zTXt,
+    gIFg,
+    gIFx,
+    oFFs,
+    pCAL,
+    sCAL,
+    sTER,
+    Other([u8; 4]),
 }
 
 impl ChunkType {
-    pub fn from_str(chunk_type: &str) -> Self {
-        match chunk_type {
-            "IHDR" => ChunkType::IHDR,
-            "IDAT" => ChunkType::IDAT,
-            "IEND" => ChunkType::IEND,
-            _ => ChunkType::Unknown,
-        }
-    }
-
     pub fn as_str(&self) -> &str {
         match self {
             ChunkType::IHDR => "IHDR",
+            ChunkType::PLTE => "PLTE",
             ChunkType::IDAT => "IDAT",
             ChunkType::IEND => "IEND",
-            ChunkType::Unknown => "unknown",
+            ChunkType::acTL => "acTL",
+            ChunkType::bKGD => "bKGD",
+            ChunkType::cHRM => "cHRM",
+            ChunkType::cICP => "cICP",
+            ChunkType::cLLI => "cLLI",
+            ChunkType::eXIf => "eXIf",
+            ChunkType::fcTL => "fcTL",
+            ChunkType::fdAT => "fdAT",
+            ChunkType::gAMA => "gAMA",
+            ChunkType::hIST => "hIST",
+            ChunkType::iCCP => "iCCP",
+            ChunkType::iTXt => "iTXt",
+            ChunkType::mDCV => "mDCV",
+            ChunkType::pHYs => "pHYs",
+            ChunkType::sBIT => "sBIT",
+            ChunkType::sPLT => "sPLT",
+            ChunkType::sRGB => "sRGB",
+            ChunkType::tEXt => "tEXt",
+            ChunkType::tIME => "tIME",
+            ChunkType::tRNS => "tRNS",
+            ChunkType::zTXt => "zTXt",
+            ChunkType::gIFg => "gIFg",
+            ChunkType::gIFx => "gIFx",
+            ChunkType::oFFs => "oFFs",
+            ChunkType::pCAL => "pCAL",
+            ChunkType::sCAL => "sCAL",
+            ChunkType::sTER => "sTER",
+            ChunkType::Other(bytes) => std::str::from_utf8(bytes).unwrap_or("invalid"),
         }
     }
 
@@ -39,27 +120,126 @@ impl ChunkType {
     pub fn as_bytes(&self) -> [u8; 4] {
         match self {
             ChunkType::IHDR => IHDR,
+            ChunkType::PLTE => PLTE,
             ChunkType::IDAT => IDAT,
             ChunkType::IEND => IEND,
-            ChunkType::Unknown => [0, 0, 0, 0],
+            ChunkType::acTL => ACTL,
+            ChunkType::bKGD => BKGD,
+            ChunkType::cHRM => CHRM,
+            ChunkType::cICP => CICP,
+            ChunkType::cLLI => CLLI,
+            ChunkType::eXIf => EXIF,
+            ChunkType::fcTL => FCTL,
+            ChunkType::fdAT => FDAT,
+            ChunkType::gAMA => GAMA,
+            ChunkType::hIST => HIST,
+            ChunkType::iCCP => ICCP,
+            ChunkType::iTXt => ITXT,
+            ChunkType::mDCV => MDCV,
+            ChunkType::pHYs => PHYS,
+            ChunkType::sBIT => SBIT,
+            ChunkType::sPLT => SPLT,
+            ChunkType::sRGB => SRGB,
+            ChunkType::tEXt => TEXT,
+            ChunkType::tIME => TIME,
+            ChunkType::tRNS => TRNS,
+            ChunkType::zTXt => ZTXT,
+            ChunkType::gIFg => GIFG,
+            ChunkType::gIFx => GIFX,
+            ChunkType::oFFs => OFFS,
+            ChunkType::pCAL => PCAL,
+            ChunkType::sCAL => SCAL,
+            ChunkType::sTER => STER,
+            ChunkType::Other(bytes) => *bytes,
         }
     }
 
     pub fn from_bytes(bytes: [u8; 4]) -> Self {
         match bytes {
             IHDR => ChunkType::IHDR,
+            PLTE => ChunkType::PLTE,
             IDAT => ChunkType::IDAT,
             IEND => ChunkType::IEND,
-            _ => ChunkType::Unknown,
+            ACTL => ChunkType::acTL,
+            BKGD => ChunkType::bKGD,
+            CHRM => ChunkType::cHRM,
+            CICP => ChunkType::cICP,
+            CLLI => ChunkType::cLLI,
+            EXIF => ChunkType::eXIf,
+            FCTL => ChunkType::fcTL,
+            FDAT => ChunkType::fdAT,
+            GAMA => ChunkType::gAMA,
+            HIST => ChunkType::hIST,
+            ICCP => ChunkType::iCCP,
+            ITXT => ChunkType::iTXt,
+            MDCV => ChunkType::mDCV,
+            PHYS => ChunkType::pHYs,
+            SBIT => ChunkType::sBIT,
+            SPLT => ChunkType::sPLT,
+            SRGB => ChunkType::sRGB,
+            TEXT => ChunkType::tEXt,
+            TIME => ChunkType::tIME,
+            TRNS => ChunkType::tRNS,
+            ZTXT => ChunkType::zTXt,
+            GIFG => ChunkType::gIFg,
+            GIFX => ChunkType::gIFx,
+            OFFS => ChunkType::oFFs,
+            PCAL => ChunkType::pCAL,
+            SCAL => ChunkType::sCAL,
+            STER => ChunkType::sTER,
+            _ => ChunkType::Other(bytes),
+        }
+    }
+
+    /// Bit 5 of the first byte: lowercase means the chunk is not needed to
+    /// display the image.
+    pub fn is_ancillary(&self) -> bool {
+        self.as_bytes()[0].is_ascii_lowercase()
+    }
+
+    /// Bit 5 of the second byte: lowercase means the chunk is not registered.
+    pub fn is_private(&self) -> bool {
+        self.as_bytes()[1].is_ascii_lowercase()
+    }
+
+    /// Bit 5 of the third byte is reserved and must be zero (uppercase).
+    pub fn is_reserved_bit_set(&self) -> bool {
+        self.as_bytes()[2].is_ascii_lowercase()
+    }
+
+    /// Bit 5 of the fourth byte: lowercase means editors that do not know the
+    /// chunk may copy it even after modifying critical chunks.
+    pub fn is_safe_to_copy(&self) -> bool {
+        self.as_bytes()[3].is_ascii_lowercase()
+    }
+}
+
+impl FromStr for ChunkType {
+    type Err = PngError;
+
+    fn from_str(chunk_type: &str) -> Result<Self, Self::Err> {
+        let bytes: [u8; 4] = chunk_type
+            .as_bytes()
+            .try_into()
+            .map_err(|_| PngError::ParseError(format!("Invalid chunk type {:?}", chunk_type)))?;
+        if !bytes.iter().all(u8::is_ascii_alphabetic) {
+            return Err(PngError::ParseError(format!(
+                "Invalid chunk type {:?}",
+                chunk_type
+            )));
         }
+        Ok(ChunkType::from_bytes(bytes))
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Chunk {
+    /// The length as read. `to_bytes` writes the length of the data instead.
     pub length: u32,
     pub chunk_type: ChunkType,
-    pub data: Vec<u8>,
+    /// Only replaceable through `set_data`, so the length and CRC are kept in
+    /// step with every edit.
+    data: Vec<u8>,
     pub crc: u32,
 }
 
@@ -73,24 +253,75 @@ impl Chunk {
         }
     }
 
+    /// Builds a chunk from its type and data, computing the length and CRC.
+    pub fn from_data(chunk_type: ChunkType, data: Vec<u8>) -> Self {
+        let crc = crc32(&chunk_type.as_bytes(), &data);
+        Self::new(data.len() as u32, chunk_type, data, crc)
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
     pub fn is_critical(&self) -> bool {
-        self.chunk_type.as_bytes()[0].is_ascii_uppercase()
+        !self.chunk_type.is_ancillary()
     }
 
     pub fn is_public(&self) -> bool {
-        self.chunk_type.as_bytes()[1].is_ascii_uppercase()
+        !self.chunk_type.is_private()
     }
 
     pub fn is_reserved_bit_valid(&self) -> bool {
-        self.chunk_type.as_bytes()[2].is_ascii_uppercase()
+        !self.chunk_type.is_reserved_bit_set()
+    }
+
+    pub fn is_safe_to_copy(&self) -> bool {
+        self.chunk_type.is_safe_to_copy()
     }
 
     pub fn verify_crc(&self) -> bool {
         crc32(&self.chunk_type.as_bytes(), &self.data) == self.crc
     }
 
+    /// Checks the CRC, the reserved bit, and that a critical chunk is one this
+    /// decoder understands. Ancillary and private chunks are valid.
+    pub fn validate(&self) -> Result<(), PngError> {
+        let computed = crc32(&self.chunk_type.as_bytes(), &self.data);
+        if computed != self.crc {
+            return Err(PngError::CrcMismatch(self.chunk_type, self.crc, computed));
+        }
+        if self.chunk_type.is_reserved_bit_set() {
+            return Err(PngError::ReservedBitSet(self.chunk_type));
+        }
+        if let ChunkType::Other(_) = self.chunk_type {
+            if self.is_critical() {
+                return Err(PngError::UnknownCriticalChunk(self.chunk_type));
+            }
+        }
+        Ok(())
+    }
+
     pub fn is_valid(&self) -> bool {
-        self.is_critical() && self.is_public() && self.is_reserved_bit_valid() && self.verify_crc()
+        self.validate().is_ok()
+    }
+
+    /// Replaces the chunk data, updating the length and CRC to match.
+    pub fn set_data(&mut self, data: Vec<u8>) {
+        *self = Self::from_data(self.chunk_type, data);
+    }
+
+    /// Serializes the chunk as length, type, data and CRC. The length is that
+    /// of the data, whatever the `length` field says, since any other value
+    /// would make the file unreadable. A chunk read with a bad CRC is written
+    /// back with the same CRC; only `set_data` and `from_data` compute a new
+    /// one.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(12 + self.data.len());
+        bytes.extend_from_slice(&(self.data.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&self.chunk_type.as_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes.extend_from_slice(&self.crc.to_be_bytes());
+        bytes
     }
 }
 
@@ -98,18 +329,26 @@ impl fmt::Display for Chunk {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "Type: {} ({} bytes), CRC: {}, Validity: {}",
+            "Type: {} ({} bytes), CRC: {}, Validity: ",
             self.chunk_type.as_str(),
             self.length,
             self.crc,
-            if self.is_valid() { "Valid" } else { "Invalid" }
-        )
+        )?;
+        match self.validate() {
+            Ok(()) => write!(f, "Valid"),
+            Err(err) => write!(f, "Invalid ({})", err),
+        }
     }
 }
 
 fn crc32(chunk_type: &[u8], data: &[u8]) -> u32 {
-    let mut crc = !0u32;
-    for &byte in chunk_type.iter().chain(data.iter()) {
+    !crc32_update(crc32_update(!0, chunk_type), data)
+}
+
+/// Feeds `bytes` into a running CRC register. Start from `!0` and invert the
+/// final register to get the chunk CRC.
+pub fn crc32_update(mut crc: u32, bytes: &[u8]) -> u32 {
+    for &byte in bytes {
         crc ^= byte as u32;
         for _ in 0..8 {
             crc = if crc & 1 != 0 {
@@ -119,5 +358,5 @@ fn crc32(chunk_type: &[u8], data: &[u8]) -> u32 {
             };
         }
     }
-    !crc
+    crc
 }
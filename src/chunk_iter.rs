@@ -0,0 +1,355 @@
+// chunk_iter.rs
+use std::io;
+use std::io::prelude::*;
+
+use crate::chunk::{crc32_update, Chunk, ChunkType};
+use crate::error::PngError;
+use crate::raw_data::RawPng;
+
+/// Chunk lengths are limited to 2^31 - 1 bytes.
+pub const MAX_CHUNK_LENGTH: u32 = 0x7FFF_FFFF;
+
+/// The length and type of a chunk, read before its data.
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkHeader {
+    /// Byte offset of the chunk from the start of the PNG stream.
+    pub offset: u64,
+    pub length: u32,
+    pub chunk_type: ChunkType,
+}
+
+/// Reads chunks lazily from a PNG stream, holding at most one chunk's data in
+/// memory. Iterating yields whole chunks; `next_header` lets callers look at a
+/// chunk's type and length first and skip its data without storing it.
+/// Iteration ends after IEND.
+pub struct ChunkIter<R> {
+    reader: R,
+    offset: u64,
+    /// Chunk whose header was read but whose data and CRC were not.
+    pending: Option<ChunkHeader>,
+    /// Bytes of the pending chunk's data already streamed by `read_data`.
+    data_read: u32,
+    /// Running CRC register over the pending chunk's type and streamed data.
+    crc: u32,
+    finished: bool,
+}
+
+impl<R: Read> ChunkIter<R> {
+    /// Reads and checks the PNG signature at the start of `reader`.
+    pub fn new(mut reader: R) -> Result<Self, PngError> {
+        let mut signature = [0; 8];
+        if read_full(&mut reader, &mut signature).map_err(PngError::Io)? < 8
+            || !RawPng::is_signature_valid(signature)
+        {
+            return Err(PngError::InvalidSignature);
+        }
+        Ok(Self {
+            reader,
+            offset: 8,
+            pending: None,
+            data_read: 0,
+            crc: !0,
+            finished: false,
+        })
+    }
+
+    /// Number of bytes consumed from the stream so far.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the underlying reader, positioned after the last consumed byte.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Reads the next chunk's length and type, skipping the data of the
+    /// previous chunk if it was not read.
+    pub fn next_header(&mut self) -> Option<Result<ChunkHeader, PngError>> {
+        let result = self.read_header();
+        if !matches!(result, Some(Ok(_))) {
+            self.finished = true;
+        }
+        result
+    }
+
+    /// Reads the data and CRC of the chunk returned by the last `next_header`.
+    pub fn read_chunk(&mut self) -> Result<Chunk, PngError> {
+        if self.data_read > 0 {
+            return Err(PngError::ParseError(
+                "Chunk data has already been streamed".to_string(),
+            ));
+        }
+        let header = self
+            .pending
+            .take()
+            .ok_or_else(|| PngError::ParseError("No chunk header has been read".to_string()))?;
+        let mut data = Vec::new();
+        let read = (&mut self.reader)
+            .take(header.length as u64)
+            .read_to_end(&mut data)
+            .map_err(PngError::Io)?;
+        self.offset += read as u64;
+        if read < header.length as usize {
+            self.finished = true;
+            return Err(PngError::TruncatedChunk(header.offset as usize));
+        }
+        let mut crc = [0; 4];
+        let read = read_full(&mut self.reader, &mut crc).map_err(PngError::Io)?;
+        self.offset += read as u64;
+        if read < 4 {
+            self.finished = true;
+            return Err(PngError::MissingCrc((self.offset - read as u64) as usize));
+        }
+        Ok(Chunk::new(
+            header.length,
+            header.chunk_type,
+            data,
+            u32::from_be_bytes(crc),
+        ))
+    }
+
+    /// Streams the data of the chunk returned by the last `next_header` into
+    /// `buffer`, returning 0 once all of it has been read. Call `finish_data`
+    /// afterwards to check the CRC.
+    pub fn read_data(&mut self, buffer: &mut [u8]) -> Result<usize, PngError> {
+        let header = self
+            .pending
+            .ok_or_else(|| PngError::ParseError("No chunk header has been read".to_string()))?;
+        let remaining = (header.length - self.data_read) as usize;
+        let wanted = remaining.min(buffer.len());
+        if wanted == 0 {
+            return Ok(0);
+        }
+        let read = read_full(&mut self.reader, &mut buffer[..wanted]).map_err(PngError::Io)?;
+        self.offset += read as u64;
+        if read == 0 {
+            self.finished = true;
+            return Err(PngError::TruncatedChunk(header.offset as usize));
+        }
+        self.crc = crc32_update(self.crc, &buffer[..read]);
+        self.data_read += read as u32;
+        Ok(read)
+    }
+
+    /// Reads the CRC of a chunk whose data was fully streamed by `read_data`
+    /// and checks it against the streamed bytes.
+    pub fn finish_data(&mut self) -> Result<(), PngError> {
+        let header = self
+            .pending
+            .take()
+            .ok_or_else(|| PngError::ParseError("No chunk header has been read".to_string()))?;
+        if self.data_read < header.length {
+            self.finished = true;
+            return Err(PngError::ParseError(
+                "Chunk data has not been fully read".to_string(),
+            ));
+        }
+        self.data_read = 0;
+        let mut crc = [0; 4];
+        let read = read_full(&mut self.reader, &mut crc).map_err(PngError::Io)?;
+        self.offset += read as u64;
+        if read < 4 {
+            self.finished = true;
+            return Err(PngError::MissingCrc((self.offset - read as u64) as usize));
+        }
+        let stored = u32::from_be_bytes(crc);
+        if stored != !self.crc {
+            self.finished = true;
+            return Err(PngError::CrcMismatch(header.chunk_type, stored, !self.crc));
+        }
+        Ok(())
+    }
+
+    fn read_header(&mut self) -> Option<Result<ChunkHeader, PngError>> {
+        if let Some(header) = self.pending.take() {
+            if let Err(err) = self.skip_data(&header) {
+                return Some(Err(err));
+            }
+        }
+        if self.finished {
+            return None;
+        }
+
+        let offset = self.offset;
+        let mut bytes = [0; 8];
+        let read = match read_full(&mut self.reader, &mut bytes) {
+            Ok(read) => read,
+            Err(err) => return Some(Err(PngError::Io(err))),
+        };
+        self.offset += read as u64;
+        match read {
+            0 => return None,
+            8 => {}
+            _ => return Some(Err(PngError::TruncatedChunk(offset as usize))),
+        }
+
+        let length = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        if length > MAX_CHUNK_LENGTH {
+            return Some(Err(PngError::ChunkLengthTooLarge(length, offset as usize)));
+        }
+        let chunk_type = ChunkType::from_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        let header = ChunkHeader {
+            offset,
+            length,
+            chunk_type,
+        };
+        self.crc = crc32_update(!0, &bytes[4..8]);
+        // Nothing after IEND belongs to the chunk stream.
+        if chunk_type.is_same_as(&ChunkType::IEND) {
+            self.finished = true;
+        }
+        self.pending = Some(header);
+        Some(Ok(header))
+    }
+
+    fn skip_data(&mut self, header: &ChunkHeader) -> Result<(), PngError> {
+        let data_left = (header.length - self.data_read) as u64;
+        self.data_read = 0;
+        let remaining = data_left + 4;
+        let skipped = io::copy(&mut (&mut self.reader).take(remaining), &mut io::sink())
+            .map_err(PngError::Io)?;
+        self.offset += skipped;
+        if skipped < data_left {
+            return Err(PngError::TruncatedChunk(header.offset as usize));
+        }
+        if skipped < remaining {
+            return Err(PngError::MissingCrc(
+                (header.offset + 8 + header.length as u64) as usize,
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for ChunkIter<R> {
+    type Item = Result<Chunk, PngError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_header()? {
+            Ok(_) => Some(self.read_chunk()),
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+/// Fills `buffer` as far as the reader allows, returning the number of bytes
+/// read. Unlike `read_exact`, a short read is not an error.
+fn read_full<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::PngReader;
+    use crate::raw_data::SIGNATURE;
+
+    /// The signature followed by a complete 3-byte tEXt chunk at offset 8,
+    /// then `rest`.
+    fn stream(rest: &[u8]) -> Vec<u8> {
+        let chunk = Chunk::from_data(ChunkType::tEXt, b"a\0b".to_vec());
+        [&SIGNATURE[..], &chunk.to_bytes(), rest].concat()
+    }
+
+    /// The first error met reading every chunk of `bytes`.
+    fn first_error(bytes: &[u8]) -> PngError {
+        ChunkIter::new(bytes)
+            .and_then(|chunks| chunks.collect::<Result<Vec<_>, _>>())
+            .unwrap_err()
+    }
+
+    /// The first error met skipping the data of every chunk of `bytes`.
+    fn first_skip_error(bytes: &[u8]) -> PngError {
+        let mut chunks = ChunkIter::new(bytes).unwrap();
+        loop {
+            match chunks.next_header() {
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return err,
+                None => panic!("no error in {:?}", bytes),
+            }
+        }
+    }
+
+    #[test]
+    fn reads_complete_chunks() {
+        let chunks: Vec<Chunk> = ChunkIter::new(&stream(&[])[..])
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].data(), b"a\0b");
+    }
+
+    #[test]
+    fn rejects_input_shorter_than_the_signature() {
+        for length in 0..8 {
+            assert!(matches!(
+                first_error(&SIGNATURE[..length]),
+                PngError::InvalidSignature
+            ));
+            assert!(matches!(
+                PngReader::from_bytes(&SIGNATURE[..length]).load_png(),
+                Err(PngError::InvalidSignature)
+            ));
+        }
+    }
+
+    #[test]
+    fn reports_a_truncated_length_and_type() {
+        // The second chunk starts at offset 8 + 15.
+        for length in 1..8 {
+            let bytes = stream(&[0, 0, 0, 0, b'I', b'E', b'N'][..length]);
+            assert!(matches!(first_error(&bytes), PngError::TruncatedChunk(23)));
+            assert!(matches!(
+                first_skip_error(&bytes),
+                PngError::TruncatedChunk(23)
+            ));
+        }
+        assert!(matches!(
+            PngReader::from_bytes(&SIGNATURE).load_png(),
+            Err(PngError::TruncatedChunk(8))
+        ));
+    }
+
+    #[test]
+    fn reports_truncated_data() {
+        let bytes = stream(&[0, 0, 0, 4, b't', b'E', b'X', b't', b'a', b'\0']);
+        assert!(matches!(first_error(&bytes), PngError::TruncatedChunk(23)));
+        assert!(matches!(
+            first_skip_error(&bytes),
+            PngError::TruncatedChunk(23)
+        ));
+    }
+
+    #[test]
+    fn reports_a_missing_crc() {
+        // The CRC of the second chunk would start at 23 + 8 + 3.
+        let bytes = stream(&[0, 0, 0, 3, b't', b'E', b'X', b't', b'a', b'\0', b'b', 1, 2]);
+        assert!(matches!(first_error(&bytes), PngError::MissingCrc(34)));
+        assert!(matches!(first_skip_error(&bytes), PngError::MissingCrc(34)));
+    }
+
+    #[test]
+    fn rejects_lengths_above_the_limit() {
+        let bytes = stream(&[0x80, 0, 0, 0, b'I', b'D', b'A', b'T']);
+        assert!(matches!(
+            first_error(&bytes),
+            PngError::ChunkLengthTooLarge(0x8000_0000, 23)
+        ));
+        let bytes = stream(&[0xFF, 0xFF, 0xFF, 0xFF, b'I', b'D', b'A', b'T']);
+        assert!(matches!(
+            first_skip_error(&bytes),
+            PngError::ChunkLengthTooLarge(0xFFFF_FFFF, 23)
+        ));
+    }
+}
@@ -0,0 +1,254 @@
+// color.rs
+use std::fmt;
+
+use crate::chunk::ChunkType;
+use crate::error::PngError;
+use crate::text;
+
+/// gAMA and cHRM values are stored as integers over this denominator.
+pub const SCALE: u32 = 100_000;
+
+/// Image gamma from a gAMA chunk, as the rational `numerator / SCALE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gamma {
+    pub numerator: u32,
+}
+
+impl Gamma {
+    pub fn new(data: &[u8]) -> Result<Gamma, PngError> {
+        let numerator = read_u32s::<1>(data, ChunkType::gAMA)?[0];
+        if numerator == 0 {
+            return Err(PngError::InvalidChunkData(
+                ChunkType::gAMA,
+                "gamma must not be zero".to_string(),
+            ));
+        }
+        Ok(Gamma { numerator })
+    }
+
+    /// The encoding exponent, e.g. 0.45455 for a typical image.
+    pub fn value(&self) -> f64 {
+        self.numerator as f64 / SCALE as f64
+    }
+}
+
+/// Gamma of sRGB images, also assumed for images without gAMA.
+pub const SRGB_GAMMA: Gamma = Gamma { numerator: 45455 };
+
+/// Lookup table mapping samples of one bit depth to gamma-corrected 8-bit
+/// values.
+#[derive(Debug, Clone)]
+pub struct GammaTable {
+    table: Vec<u8>,
+}
+
+impl GammaTable {
+    /// `file_gamma` is the image gamma from gAMA and `display_gamma` the
+    /// exponent of the display, 2.2 for typical monitors. Samples are raised
+    /// to `1 / (file_gamma * display_gamma)`.
+    pub fn new(file_gamma: f64, display_gamma: f64, bit_depth: usize) -> Self {
+        let max = ((1u32 << bit_depth) - 1) as f64;
+        let exponent = 1.0 / (file_gamma * display_gamma);
+        let table = (0..=max as u32)
+            .map(|sample| ((sample as f64 / max).powf(exponent) * 255.0).round() as u8)
+            .collect();
+        GammaTable { table }
+    }
+
+    pub fn correct(&self, sample: u16) -> u8 {
+        self.table[sample as usize]
+    }
+}
+
+/// CIE 1931 x,y chromaticities from a cHRM chunk, each scaled by `SCALE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chromaticities {
+    pub white: (u32, u32),
+    pub red: (u32, u32),
+    pub green: (u32, u32),
+    pub blue: (u32, u32),
+}
+
+impl Chromaticities {
+    pub fn new(data: &[u8]) -> Result<Chromaticities, PngError> {
+        let values = read_u32s::<8>(data, ChunkType::cHRM)?;
+        Ok(Chromaticities {
+            white: (values[0], values[1]),
+            red: (values[2], values[3]),
+            green: (values[4], values[5]),
+            blue: (values[6], values[7]),
+        })
+    }
+}
+
+/// Rendering intent from an sRGB chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderingIntent {
+    Perceptual,
+    RelativeColorimetric,
+    Saturation,
+    AbsoluteColorimetric,
+}
+
+impl RenderingIntent {
+    pub fn new(data: &[u8]) -> Result<RenderingIntent, PngError> {
+        if data.len() != 1 {
+            return Err(PngError::InvalidChunkLength(ChunkType::sRGB, data.len()));
+        }
+        RenderingIntent::from_u8(data[0]).ok_or_else(|| {
+            PngError::InvalidChunkData(
+                ChunkType::sRGB,
+                format!("unknown rendering intent {}", data[0]),
+            )
+        })
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(RenderingIntent::Perceptual),
+            1 => Some(RenderingIntent::RelativeColorimetric),
+            2 => Some(RenderingIntent::Saturation),
+            3 => Some(RenderingIntent::AbsoluteColorimetric),
+            _ => None,
+        }
+    }
+
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            RenderingIntent::Perceptual => 0,
+            RenderingIntent::RelativeColorimetric => 1,
+            RenderingIntent::Saturation => 2,
+            RenderingIntent::AbsoluteColorimetric => 3,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            RenderingIntent::Perceptual => "Perceptual",
+            RenderingIntent::RelativeColorimetric => "Relative colorimetric",
+            RenderingIntent::Saturation => "Saturation",
+            RenderingIntent::AbsoluteColorimetric => "Absolute colorimetric",
+        }
+    }
+}
+
+/// Embedded ICC profile from an iCCP chunk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IccProfile {
+    pub name: String,
+    /// The decompressed profile.
+    pub profile: Vec<u8>,
+}
+
+impl IccProfile {
+    pub fn new(data: &[u8]) -> Result<IccProfile, PngError> {
+        let invalid =
+            |reason: &str| PngError::InvalidChunkData(ChunkType::iCCP, reason.to_string());
+        let (name, rest) = text::split_null(data).ok_or_else(|| invalid("missing profile name"))?;
+        let (&method, compressed) = rest
+            .split_first()
+            .ok_or_else(|| invalid("missing compression method"))?;
+        if method != 0 {
+            return Err(PngError::InvalidCompressionMethod(method));
+        }
+        let profile =
+            text::decompress(compressed, text::MAX_DECOMPRESSED_LENGTH).map_err(invalid)?;
+        Ok(IccProfile {
+            name: text::latin1_to_string(name),
+            profile,
+        })
+    }
+}
+
+/// Coding-independent code points from a cICP chunk, as defined by ITU-T H.273.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cicp {
+    pub color_primaries: u8,
+    pub transfer_function: u8,
+    pub matrix_coefficients: u8,
+    pub full_range: bool,
+}
+
+impl Cicp {
+    pub fn new(data: &[u8]) -> Result<Cicp, PngError> {
+        if data.len() != 4 {
+            return Err(PngError::InvalidChunkLength(ChunkType::cICP, data.len()));
+        }
+        let invalid = |reason: String| PngError::InvalidChunkData(ChunkType::cICP, reason);
+        // PNG only stores RGB samples, so no YCbCr matrix can apply.
+        if data[2] != 0 {
+            return Err(invalid(format!(
+                "matrix coefficients must be 0, found {}",
+                data[2]
+            )));
+        }
+        let full_range = match data[3] {
+            0 => false,
+            1 => true,
+            flag => return Err(invalid(format!("invalid full range flag {}", flag))),
+        };
+        Ok(Cicp {
+            color_primaries: data[0],
+            transfer_function: data[1],
+            matrix_coefficients: data[2],
+            full_range,
+        })
+    }
+}
+
+/// How decoded samples should be interpreted, resolved from the colorimetry
+/// chunks by `RawPng::color_space`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColorSpace {
+    Cicp(Cicp),
+    Icc(IccProfile),
+    Srgb(RenderingIntent),
+    /// Described by gAMA and/or cHRM.
+    Calibrated {
+        gamma: Option<Gamma>,
+        chromaticities: Option<Chromaticities>,
+    },
+    /// No colorimetry chunks; decoders usually assume sRGB.
+    Unspecified,
+}
+
+impl fmt::Display for ColorSpace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ColorSpace::Cicp(cicp) => write!(
+                f,
+                "cICP (primaries {}, transfer {}, matrix {}, {} range)",
+                cicp.color_primaries,
+                cicp.transfer_function,
+                cicp.matrix_coefficients,
+                if cicp.full_range { "full" } else { "narrow" }
+            ),
+            ColorSpace::Icc(icc) => {
+                write!(
+                    f,
+                    "ICC profile {:?} ({} bytes)",
+                    icc.name,
+                    icc.profile.len()
+                )
+            }
+            ColorSpace::Srgb(intent) => write!(f, "sRGB ({})", intent.as_str()),
+            ColorSpace::Calibrated { gamma, .. } => match gamma {
+                Some(gamma) => write!(f, "Calibrated (gamma {:.5})", gamma.value()),
+                None => write!(f, "Calibrated (chromaticities only)"),
+            },
+            ColorSpace::Unspecified => write!(f, "Unspecified"),
+        }
+    }
+}
+
+/// Reads the big-endian integers making up a fixed-size chunk.
+fn read_u32s<const N: usize>(data: &[u8], chunk_type: ChunkType) -> Result<[u32; N], PngError> {
+    if data.len() != N * 4 {
+        return Err(PngError::InvalidChunkLength(chunk_type, data.len()));
+    }
+    let mut values = [0; N];
+    for (value, bytes) in values.iter_mut().zip(data.chunks_exact(4)) {
+        *value = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    Ok(values)
+}
@@ -1,12 +1,86 @@
 use std::io;
 
+use crate::chunk::ChunkType;
+
 #[derive(Debug)]
 pub enum PngError {
     Io(io::Error),
     InvalidSignature,
-    InvalidChunkSequence,
+    InvalidChunkSequence(Vec<SequenceViolation>),
     InvalidData,
     ParseError(String),
+    InvalidPaletteLength(usize),
+    MissingPalette,
+    PaletteIndexOutOfRange(u8, usize),
+    InvalidTransparency(u8, usize),
+    CrcMismatch(ChunkType, u32, u32),
+    ReservedBitSet(ChunkType),
+    UnknownCriticalChunk(ChunkType),
+    TruncatedChunk(usize),
+    ChunkLengthTooLarge(u32, usize),
+    MissingCrc(usize),
+    FirstChunkNotIhdr(ChunkType),
+    InvalidDimensions(u32, u32),
+    InvalidColorType(u8),
+    InvalidBitDepth(u8, u8),
+    InvalidCompressionMethod(u8),
+    InvalidFilterMethod(u8),
+    InvalidInterlaceMethod(u8),
+    InvalidImageDataLength(usize, usize),
+    InvalidFilterType(u8),
+    InvalidDisplayGamma(f64),
+    InvalidKeyword(String),
+    InvalidTextChunk(ChunkType, String),
+    InvalidChunkLength(ChunkType, usize),
+    InvalidChunkData(ChunkType, String),
+}
+
+/// A single breach of the PNG chunk ordering rules.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SequenceViolation {
+    MissingChunk(ChunkType),
+    ForbiddenChunk(ChunkType),
+    DuplicateChunk(ChunkType),
+    NonConsecutiveIdat,
+    ChunkAfterIend(ChunkType),
+    MustPrecede(ChunkType, ChunkType),
+    MustFollow(ChunkType, ChunkType),
+}
+
+impl std::fmt::Display for SequenceViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SequenceViolation::MissingChunk(chunk_type) => {
+                write!(f, "Missing {} chunk", chunk_type.as_str())
+            }
+            SequenceViolation::ForbiddenChunk(chunk_type) => {
+                write!(
+                    f,
+                    "{} chunk not allowed for this color type",
+                    chunk_type.as_str()
+                )
+            }
+            SequenceViolation::DuplicateChunk(chunk_type) => {
+                write!(f, "{} chunk appears more than once", chunk_type.as_str())
+            }
+            SequenceViolation::NonConsecutiveIdat => write!(f, "IDAT chunks are not consecutive"),
+            SequenceViolation::ChunkAfterIend(chunk_type) => {
+                write!(f, "{} chunk after IEND", chunk_type.as_str())
+            }
+            SequenceViolation::MustPrecede(chunk_type, other) => write!(
+                f,
+                "{} chunk must come before {}",
+                chunk_type.as_str(),
+                other.as_str()
+            ),
+            SequenceViolation::MustFollow(chunk_type, other) => write!(
+                f,
+                "{} chunk must come after {}",
+                chunk_type.as_str(),
+                other.as_str()
+            ),
+        }
+    }
 }
 
 impl From<io::Error> for PngError {
@@ -37,10 +111,110 @@ impl std::fmt::Display for PngError {
             PngError::Io(err) => write!(f, "IO Error: {}", err),
             PngError::InvalidSignature => write!(f, "Invalid PNG Signature"),
             PngError::ParseError(err) => write!(f, "Parse Error: {}", err),
-            PngError::InvalidChunkSequence => write!(f, "Invalid Chunk Sequence"),
+            PngError::InvalidChunkSequence(violations) => {
+                write!(f, "Invalid Chunk Sequence:")?;
+                for violation in violations {
+                    write!(f, "\n\t{}", violation)?;
+                }
+                Ok(())
+            }
             PngError::InvalidData => {
                 write!(f, "Invalid Data: Insufficient bytes for header information")
             }
+            PngError::InvalidPaletteLength(len) => {
+                write!(
+                    f,
+                    "Invalid Palette: {} bytes is not 1 to 256 RGB entries",
+                    len
+                )
+            }
+            PngError::MissingPalette => write!(f, "Missing PLTE chunk for indexed-color image"),
+            PngError::PaletteIndexOutOfRange(index, size) => write!(
+                f,
+                "Palette index {} out of range for palette with {} entries",
+                index, size
+            ),
+            PngError::InvalidTransparency(color_type, len) => write!(
+                f,
+                "Invalid Transparency: {} bytes of tRNS data for color type {}",
+                len, color_type
+            ),
+            PngError::CrcMismatch(chunk_type, stored, computed) => write!(
+                f,
+                "CRC mismatch in {} chunk: stored {:#010x}, computed {:#010x}",
+                chunk_type.as_str(),
+                stored,
+                computed
+            ),
+            PngError::ReservedBitSet(chunk_type) => {
+                write!(f, "Reserved bit set in {} chunk type", chunk_type.as_str())
+            }
+            PngError::UnknownCriticalChunk(chunk_type) => {
+                write!(f, "Unknown critical chunk {}", chunk_type.as_str())
+            }
+            PngError::TruncatedChunk(offset) => {
+                write!(f, "Truncated chunk at byte offset {}", offset)
+            }
+            PngError::ChunkLengthTooLarge(length, offset) => write!(
+                f,
+                "Chunk length {} at byte offset {} exceeds 2^31-1",
+                length, offset
+            ),
+            PngError::MissingCrc(offset) => write!(f, "Missing CRC at byte offset {}", offset),
+            PngError::FirstChunkNotIhdr(chunk_type) => {
+                write!(
+                    f,
+                    "Expected IHDR as first chunk, found {}",
+                    chunk_type.as_str()
+                )
+            }
+            PngError::InvalidDimensions(width, height) => {
+                write!(f, "Invalid image dimensions {}x{}", width, height)
+            }
+            PngError::InvalidColorType(color_type) => {
+                write!(f, "Invalid color type {}", color_type)
+            }
+            PngError::InvalidBitDepth(color_type, bit_depth) => write!(
+                f,
+                "Bit depth {} is not allowed for color type {}",
+                bit_depth, color_type
+            ),
+            PngError::InvalidCompressionMethod(method) => {
+                write!(f, "Invalid compression method {}", method)
+            }
+            PngError::InvalidFilterMethod(method) => write!(f, "Invalid filter method {}", method),
+            PngError::InvalidInterlaceMethod(method) => {
+                write!(f, "Invalid interlace method {}", method)
+            }
+            PngError::InvalidImageDataLength(length, expected) => write!(
+                f,
+                "Image data is {} bytes, expected {} for the header dimensions",
+                length, expected
+            ),
+            PngError::InvalidFilterType(filter_type) => {
+                write!(f, "Invalid filter type {}: expected 0 to 4", filter_type)
+            }
+            PngError::InvalidDisplayGamma(gamma) => {
+                write!(
+                    f,
+                    "Invalid display gamma {}: expected a positive number",
+                    gamma
+                )
+            }
+            PngError::InvalidKeyword(keyword) => write!(
+                f,
+                "Invalid keyword {:?}: expected 1 to 79 printable Latin-1 characters",
+                keyword
+            ),
+            PngError::InvalidTextChunk(chunk_type, reason) => {
+                write!(f, "Invalid {} chunk: {}", chunk_type.as_str(), reason)
+            }
+            PngError::InvalidChunkLength(chunk_type, length) => {
+                write!(f, "Invalid {} chunk length {}", chunk_type.as_str(), length)
+            }
+            PngError::InvalidChunkData(chunk_type, reason) => {
+                write!(f, "Invalid {} chunk: {}", chunk_type.as_str(), reason)
+            }
         }
     }
 }
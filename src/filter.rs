@@ -0,0 +1,167 @@
+// filter.rs
+use std::io;
+
+/// How the encoder picks the filter type of each scanline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterStrategy {
+    /// The same filter type (0 to 4) for every scanline.
+    Fixed(u8),
+    /// The filter whose output has the smallest sum of absolute values, with
+    /// bytes read as signed. This is the heuristic the PNG specification
+    /// recommends.
+    MinimumSum,
+    /// The filter whose output has the lowest Shannon entropy.
+    Entropy,
+    /// The filter whose output compresses smallest, following the previous
+    /// scanline. Slowest, but usually gives the smallest files.
+    BruteForce,
+}
+
+/// Reverses the filter `filter_type` applied to `scanline`, given the already
+/// unfiltered previous scanline (all zeros for the first one).
+pub fn unfilter_scanline(
+    filter_type: u8,
+    scanline: &[u8],
+    previous: &[u8],
+    bytes_per_pixel: usize,
+) -> io::Result<Vec<u8>> {
+    match filter_type {
+        0 => unfilter_none(scanline),
+        1 => unfilter_sub(scanline, bytes_per_pixel),
+        2 => unfilter_up(scanline, previous),
+        3 => unfilter_average(scanline, previous, bytes_per_pixel),
+        4 => unfilter_paeth(scanline, previous, bytes_per_pixel),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid filter type {}", filter_type),
+        )),
+    }
+}
+
+/// Applies filter `filter_type` to `scanline`, given the unfiltered previous
+/// scanline (all zeros for the first one). The result excludes the filter
+/// type byte.
+pub fn filter_scanline(
+    filter_type: u8,
+    scanline: &[u8],
+    previous: &[u8],
+    bytes_per_pixel: usize,
+) -> io::Result<Vec<u8>> {
+    let predictor: fn(u8, u8, u8) -> u8 = match filter_type {
+        0 => |_, _, _| 0,
+        1 => |a, _, _| a,
+        2 => |_, b, _| b,
+        3 => |a, b, _| ((a as u16 + b as u16) / 2) as u8,
+        4 => paeth_predictor,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid filter type {}", filter_type),
+            ))
+        }
+    };
+    Ok(scanline
+        .iter()
+        .enumerate()
+        .map(|(i, &x)| {
+            let (a, c) = if i >= bytes_per_pixel {
+                (scanline[i - bytes_per_pixel], previous[i - bytes_per_pixel])
+            } else {
+                (0, 0)
+            };
+            x.wrapping_sub(predictor(a, previous[i], c))
+        })
+        .collect())
+}
+
+/// Sum of the absolute values of filtered bytes read as signed, the
+/// `MinimumSum` cost of a scanline.
+pub fn absolute_sum(filtered: &[u8]) -> u64 {
+    filtered
+        .iter()
+        .map(|&byte| (byte as i8).unsigned_abs() as u64)
+        .sum()
+}
+
+/// Shannon entropy in bits per byte, the `Entropy` cost of a scanline.
+pub fn entropy(filtered: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &byte in filtered {
+        counts[byte as usize] += 1;
+    }
+    let total = filtered.len() as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let probability = count as f64 / total;
+            -probability * probability.log2()
+        })
+        .sum()
+}
+
+fn unfilter_none(scanline: &[u8]) -> io::Result<Vec<u8>> {
+    Ok(scanline.to_vec())
+}
+
+fn unfilter_sub(scanline: &[u8], bytes_per_pixel: usize) -> io::Result<Vec<u8>> {
+    let mut result = scanline.to_vec();
+    for i in bytes_per_pixel..result.len() {
+        result[i] = result[i].wrapping_add(result[i - bytes_per_pixel]);
+    }
+    Ok(result)
+}
+
+fn unfilter_up(scanline: &[u8], previous: &[u8]) -> io::Result<Vec<u8>> {
+    Ok(scanline
+        .iter()
+        .zip(previous)
+        .map(|(&x, &b)| x.wrapping_add(b))
+        .collect())
+}
+
+fn unfilter_average(
+    scanline: &[u8],
+    previous: &[u8],
+    bytes_per_pixel: usize,
+) -> io::Result<Vec<u8>> {
+    let mut result = scanline.to_vec();
+    for i in 0..result.len() {
+        let a = if i >= bytes_per_pixel {
+            result[i - bytes_per_pixel] as u16
+        } else {
+            0
+        };
+        let b = previous[i] as u16;
+        result[i] = result[i].wrapping_add(((a + b) / 2) as u8);
+    }
+    Ok(result)
+}
+
+fn unfilter_paeth(scanline: &[u8], previous: &[u8], bytes_per_pixel: usize) -> io::Result<Vec<u8>> {
+    let mut result = scanline.to_vec();
+    for i in 0..result.len() {
+        let (a, c) = if i >= bytes_per_pixel {
+            (result[i - bytes_per_pixel], previous[i - bytes_per_pixel])
+        } else {
+            (0, 0)
+        };
+        let b = previous[i];
+        result[i] = result[i].wrapping_add(paeth_predictor(a, b, c));
+    }
+    Ok(result)
+}
+
+fn paeth_predictor(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
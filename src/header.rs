@@ -1,30 +1,218 @@
 // header.rs
+use std::fmt;
 
+use crate::chunk::{Chunk, ChunkType};
 use crate::error::PngError;
+
+/// Image dimensions are limited to 2^31 - 1 pixels.
+const MAX_DIMENSION: u32 = 0x7FFF_FFFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType {
+    Grayscale,
+    Rgb,
+    Indexed,
+    GrayscaleAlpha,
+    Rgba,
+}
+
+impl ColorType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(ColorType::Grayscale),
+            2 => Some(ColorType::Rgb),
+            3 => Some(ColorType::Indexed),
+            4 => Some(ColorType::GrayscaleAlpha),
+            6 => Some(ColorType::Rgba),
+            _ => None,
+        }
+    }
+
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            ColorType::Grayscale => 0,
+            ColorType::Rgb => 2,
+            ColorType::Indexed => 3,
+            ColorType::GrayscaleAlpha => 4,
+            ColorType::Rgba => 6,
+        }
+    }
+
+    /// Number of samples stored for each pixel.
+    pub fn channels(&self) -> usize {
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+
+    /// Whether pixels carry an alpha sample. Transparency from a tRNS chunk
+    /// is not counted.
+    pub fn has_alpha(&self) -> bool {
+        matches!(self, ColorType::GrayscaleAlpha | ColorType::Rgba)
+    }
+
+    pub fn is_grayscale(&self) -> bool {
+        matches!(self, ColorType::Grayscale | ColorType::GrayscaleAlpha)
+    }
+
+    /// Whether samples are palette indices rather than color values.
+    pub fn is_indexed(&self) -> bool {
+        matches!(self, ColorType::Indexed)
+    }
+
+    pub fn allowed_bit_depths(&self) -> &'static [BitDepth] {
+        match self {
+            ColorType::Grayscale => &[
+                BitDepth::One,
+                BitDepth::Two,
+                BitDepth::Four,
+                BitDepth::Eight,
+                BitDepth::Sixteen,
+            ],
+            ColorType::Indexed => &[
+                BitDepth::One,
+                BitDepth::Two,
+                BitDepth::Four,
+                BitDepth::Eight,
+            ],
+            ColorType::Rgb | ColorType::GrayscaleAlpha | ColorType::Rgba => {
+                &[BitDepth::Eight, BitDepth::Sixteen]
+            }
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            ColorType::Grayscale => "Grayscale",
+            ColorType::Rgb => "R,G,B triple",
+            ColorType::Indexed => "Indexed-color",
+            ColorType::GrayscaleAlpha => "Grayscale with alpha",
+            ColorType::Rgba => "R,G,B triple with alpha",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitDepth {
+    One,
+    Two,
+    Four,
+    Eight,
+    Sixteen,
+}
+
+impl BitDepth {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(BitDepth::One),
+            2 => Some(BitDepth::Two),
+            4 => Some(BitDepth::Four),
+            8 => Some(BitDepth::Eight),
+            16 => Some(BitDepth::Sixteen),
+            _ => None,
+        }
+    }
+
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            BitDepth::One => 1,
+            BitDepth::Two => 2,
+            BitDepth::Four => 4,
+            BitDepth::Eight => 8,
+            BitDepth::Sixteen => 16,
+        }
+    }
+
+    /// Number of bits in each sample.
+    pub fn bits(&self) -> usize {
+        self.as_u8() as usize
+    }
+}
+
+/// The IHDR filter method. Only method 0, adaptive filtering with the five
+/// per-scanline filter types, is defined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterMethod {
+    Adaptive,
+}
+
+impl FilterMethod {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(FilterMethod::Adaptive),
+            _ => None,
+        }
+    }
+
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            FilterMethod::Adaptive => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterlaceMethod {
+    None,
+    Adam7,
+}
+
+impl InterlaceMethod {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(InterlaceMethod::None),
+            1 => Some(InterlaceMethod::Adam7),
+            _ => None,
+        }
+    }
+
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            InterlaceMethod::None => 0,
+            InterlaceMethod::Adam7 => 1,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct HeaderInfo {
     pub width: u32,
     pub height: u32,
-    pub bit_depth: u8,
-    pub color_type: u8,
+    pub bit_depth: BitDepth,
+    pub color_type: ColorType,
     pub compression_method: u8,
-    pub filter_method: u8,
-    pub interlace_method: u8,
+    pub filter_method: FilterMethod,
+    pub interlace_method: InterlaceMethod,
+    /// The IHDR chunk this header was read from, kept so it can be written back unchanged.
+    pub chunk: Chunk,
 }
 
 impl HeaderInfo {
     pub fn new(data: &[u8]) -> Result<HeaderInfo, PngError> {
-        if data.len() < 13 {
-            return Err(PngError::InvalidData);
+        if data.len() != 13 {
+            return Err(PngError::InvalidChunkLength(ChunkType::IHDR, data.len()));
         }
 
         let width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
         let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
-        let bit_depth = data[8];
-        let color_type = data[9];
+        if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+            return Err(PngError::InvalidDimensions(width, height));
+        }
+        let color_type = ColorType::from_u8(data[9]).ok_or(PngError::InvalidColorType(data[9]))?;
+        let bit_depth = BitDepth::from_u8(data[8])
+            .filter(|bit_depth| color_type.allowed_bit_depths().contains(bit_depth))
+            .ok_or(PngError::InvalidBitDepth(data[9], data[8]))?;
         let compression_method = data[10];
-        let filter_method = data[11];
-        let interlace_method = data[12];
+        if compression_method != 0 {
+            return Err(PngError::InvalidCompressionMethod(compression_method));
+        }
+        let filter_method =
+            FilterMethod::from_u8(data[11]).ok_or(PngError::InvalidFilterMethod(data[11]))?;
+        let interlace_method =
+            InterlaceMethod::from_u8(data[12]).ok_or(PngError::InvalidInterlaceMethod(data[12]))?;
 
         Ok(HeaderInfo {
             width,
@@ -34,8 +222,60 @@ impl HeaderInfo {
             compression_method,
             filter_method,
             interlace_method,
+            chunk: Chunk::from_data(ChunkType::IHDR, data.to_vec()),
         })
     }
+
+    /// Number of samples per pixel.
+    pub fn channels(&self) -> usize {
+        self.color_type.channels()
+    }
+
+    pub fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth.bits()
+    }
+
+    /// Distance in bytes to the corresponding byte of the previous pixel, as
+    /// used by the scanline filters. Sub-byte pixels count as one byte.
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8).max(1)
+    }
+
+    /// Length in bytes of one unfiltered scanline, without the filter type byte.
+    pub fn scanline_length(&self) -> usize {
+        self.pass_scanline_length(self.width as usize)
+    }
+
+    /// Length in bytes of an unfiltered scanline `width` pixels wide, such as
+    /// a row of an Adam7 pass, without the filter type byte.
+    pub fn pass_scanline_length(&self, width: usize) -> usize {
+        (width * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    /// Builds an IHDR chunk from the current field values.
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = Vec::with_capacity(13);
+        data.extend_from_slice(&self.width.to_be_bytes());
+        data.extend_from_slice(&self.height.to_be_bytes());
+        data.extend_from_slice(&[
+            self.bit_depth.as_u8(),
+            self.color_type.as_u8(),
+            self.compression_method,
+            self.filter_method.as_u8(),
+            self.interlace_method.as_u8(),
+        ]);
+        Chunk::from_data(ChunkType::IHDR, data)
+    }
+
+    /// Parses the first chunk of a file, which must be an IHDR chunk with a valid CRC.
+    pub fn from_chunk(chunk: Chunk) -> Result<HeaderInfo, PngError> {
+        if !chunk.chunk_type.is_same_as(&ChunkType::IHDR) {
+            return Err(PngError::FirstChunkNotIhdr(chunk.chunk_type));
+        }
+        chunk.validate()?;
+        let header = HeaderInfo::new(chunk.data())?;
+        Ok(HeaderInfo { chunk, ..header })
+    }
     // pub fn new(data: &[u8]) -> Result<Option<HeaderInfo>, PngError> {
     //     if data.len() < 13 {
     //         return Err(PngError::InvalidData);
@@ -59,40 +299,28 @@ impl HeaderInfo {
     //         interlace_method,
     //     }))
     // }
+}
 
-    pub fn to_string(&self) -> String {
-        let color_type = match self.color_type {
-            0 => "Grayscale",
-            2 => "R,G,B triple",
-            3 => "Indexed-color",
-            4 => "Grayscale with alpha",
-            6 => "R,G,B triple with alpha",
-            _ => "Unknown",
-        };
-
+impl fmt::Display for HeaderInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let compression_method = match self.compression_method {
             0 => "Deflate/inflate",
             _ => "Unknown",
         };
 
         let filter_method = match self.filter_method {
-            0 => "None",
-            1 => "Sub",
-            2 => "Up",
-            3 => "Average",
-            4 => "Paeth",
-            _ => "Unknown",
+            FilterMethod::Adaptive => "Adaptive (None, Sub, Up, Average, Paeth)",
         };
 
         let interlace_method = match self.interlace_method {
-            0 => "None",
-            1 => "Adam7",
-            _ => "Unknown",
+            InterlaceMethod::None => "None",
+            InterlaceMethod::Adam7 => "Adam7",
         };
 
-        format!(
+        write!(
+            f,
             "\n\tWidth: {}\n\tHeight: {}\n\tBit depth: {}\n\tColor type: {}\n\tCompression method: {}\n\tFilter method: {}\n\tInterlace method: {}",
-            self.width, self.height, self.bit_depth, color_type, compression_method, filter_method, interlace_method
+            self.width, self.height, self.bit_depth.as_u8(), self.color_type.as_str(), compression_method, filter_method, interlace_method
         )
     }
 }
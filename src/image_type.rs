@@ -64,9 +64,9 @@ impl Display for VisualData {
     }
 }
 
-impl Into<Vec<u8>> for &VisualData {
-    fn into(self) -> Vec<u8> {
-        match self {
+impl From<&VisualData> for Vec<u8> {
+    fn from(visual_data: &VisualData) -> Self {
+        match visual_data {
             VisualData::RGBA(png) => png.data.clone(),
             VisualData::Brightness(_) => panic!("Cannot convert Brightness to PNG"),
             VisualData::Charimage(_) => panic!("Cannot convert CharImage to PNG"),
@@ -74,9 +74,9 @@ impl Into<Vec<u8>> for &VisualData {
     }
 }
 
-impl Into<Vec<f32>> for &VisualData {
-    fn into(self) -> Vec<f32> {
-        match self {
+impl From<&VisualData> for Vec<f32> {
+    fn from(visual_data: &VisualData) -> Self {
+        match visual_data {
             VisualData::RGBA(_) => panic!("Cannot convert PNG to Brightness"),
             VisualData::Brightness(brightness) => brightness.data.clone(),
             VisualData::Charimage(_) => panic!("Cannot convert CharImage to Brightness"),
@@ -84,9 +84,9 @@ impl Into<Vec<f32>> for &VisualData {
     }
 }
 
-impl Into<Vec<char>> for &VisualData {
-    fn into(self) -> Vec<char> {
-        match self {
+impl From<&VisualData> for Vec<char> {
+    fn from(visual_data: &VisualData) -> Self {
+        match visual_data {
             VisualData::RGBA(_) => panic!("Cannot convert PNG to CharImage"),
             VisualData::Charimage(char_image) => char_image.data.clone(),
             VisualData::Brightness(brightness) => {
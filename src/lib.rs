@@ -0,0 +1,40 @@
+// lib.rs
+//! PNG parsing and decoding: chunk-level access to a file through [`RawPng`]
+//! and pixel decoding through [`PngReader`]. [`PngWriter`] encodes images.
+#![allow(clippy::upper_case_acronyms)]
+
+mod chunk;
+mod chunk_iter;
+mod color;
+mod error;
+mod filter;
+mod header;
+mod image_type;
+mod optimizer;
+mod palette;
+mod physical;
+mod png;
+mod raw_data;
+mod row_decoder;
+mod text;
+mod timestamp;
+mod transparency;
+mod writer;
+
+pub use chunk::{Chunk, ChunkType};
+pub use chunk_iter::{ChunkHeader, ChunkIter};
+pub use color::{Chromaticities, Cicp, ColorSpace, Gamma, IccProfile, RenderingIntent};
+pub use error::{PngError, SequenceViolation};
+pub use filter::FilterStrategy;
+pub use header::{BitDepth, ColorType, FilterMethod, HeaderInfo, InterlaceMethod};
+pub use image_type::{Brightness, CharImage, VisualData, PNG};
+pub use optimizer::Optimizer;
+pub use palette::Palette;
+pub use physical::{PhysicalDimensions, PhysicalUnit};
+pub use png::PngReader;
+pub use raw_data::RawPng;
+pub use row_decoder::RowDecoder;
+pub use text::TextChunk;
+pub use timestamp::ModificationTime;
+pub use transparency::Transparency;
+pub use writer::PngWriter;
@@ -0,0 +1,574 @@
+// optimizer.rs
+use std::collections::HashSet;
+
+use crate::chunk::{Chunk, ChunkType};
+use crate::error::PngError;
+use crate::filter::FilterStrategy;
+use crate::header::{ColorType, HeaderInfo};
+use crate::png::{read_sample, PngReader};
+use crate::raw_data::RawPng;
+use crate::writer::PngWriter;
+
+/// Chunks whose data depend on the color type or bit depth. Images keeping
+/// any of them are only recompressed, never reduced.
+const FORMAT_DEPENDENT: [ChunkType; 3] = [ChunkType::bKGD, ChunkType::hIST, ChunkType::sBIT];
+
+/// Animation chunks refer to the frame data, so animated images are left
+/// untouched.
+const ANIMATION: [ChunkType; 3] = [ChunkType::acTL, ChunkType::fcTL, ChunkType::fdAT];
+
+/// A pixel as 16-bit RGBA, the common form used to check that two encodings
+/// hold the same image.
+type Pixel = [u16; 4];
+
+/// One way of storing the image: IHDR data, the chunks to write before IDAT
+/// and the unfiltered image data.
+struct Candidate {
+    header: [u8; 13],
+    chunks: Vec<Chunk>,
+    image: Vec<u8>,
+}
+
+/// Losslessly shrinks PNG files by trying smaller color types and bit depths,
+/// filter strategies and compression levels, keeping the smallest result
+/// that decodes to the same pixels.
+pub struct Optimizer {
+    /// Ancillary chunk types removed from the output.
+    pub strip: Vec<ChunkType>,
+    /// Whether to try color type and bit depth reductions.
+    pub reduce: bool,
+    pub filter_strategies: Vec<FilterStrategy>,
+    pub compression_levels: Vec<u32>,
+}
+
+impl Default for Optimizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Optimizer {
+    pub fn new() -> Self {
+        Self {
+            strip: Vec::new(),
+            reduce: true,
+            filter_strategies: vec![
+                FilterStrategy::Fixed(0),
+                FilterStrategy::MinimumSum,
+                FilterStrategy::Entropy,
+            ],
+            compression_levels: vec![9],
+        }
+    }
+
+    /// Returns the smallest encoding of the PNG file `png`. When nothing
+    /// smaller is found, `png` is returned unchanged, including any data
+    /// after IEND; otherwise that data is dropped, along with unknown chunks
+    /// that are not safe to copy once the image data changes.
+    pub fn optimize(&self, png: &[u8]) -> Result<Vec<u8>, PngError> {
+        let reader = PngReader::from_bytes(png);
+        let raw_png = PngReader::from_bytes(png).load_png()?;
+        if raw_png
+            .chunks
+            .iter()
+            .any(|chunk| ANIMATION.contains(&chunk.chunk_type))
+        {
+            return Ok(png.to_vec());
+        }
+        let image = reader.image_data(&raw_png)?;
+        let pixels = Self::expand_pixels(&image, &raw_png)?;
+
+        // Malformed tRNS and suggested PLTE chunks are ignored when decoding,
+        // and are dropped so they are not written into the output.
+        let bad_transparency = raw_png.transparency().is_err();
+        let bad_suggested_palette =
+            !raw_png.header.color_type.is_indexed() && raw_png.palette().is_err();
+        let chunks: Vec<Chunk> = raw_png
+            .chunks
+            .iter()
+            .filter(|chunk| {
+                let stripped =
+                    chunk.chunk_type.is_ancillary() && self.strip.contains(&chunk.chunk_type);
+                let malformed = match chunk.chunk_type {
+                    ChunkType::tRNS => bad_transparency,
+                    ChunkType::PLTE => bad_suggested_palette,
+                    _ => false,
+                };
+                // Re-encoding always rewrites IDAT, which unknown chunks
+                // without the safe-to-copy bit may depend on.
+                let unsafe_to_copy =
+                    matches!(chunk.chunk_type, ChunkType::Other(_)) && !chunk.is_safe_to_copy();
+                !stripped
+                    && !malformed
+                    && !unsafe_to_copy
+                    && !matches!(chunk.chunk_type, ChunkType::IDAT | ChunkType::IEND)
+            })
+            .cloned()
+            .collect();
+        let header: [u8; 13] = raw_png.header.to_chunk().data().try_into().unwrap();
+        let mut candidates = Vec::new();
+        if self.reduce && Self::is_reducible(&raw_png.header, &chunks) {
+            candidates = Self::reduced_candidates(&pixels, &raw_png.header, &chunks);
+        }
+        candidates.retain(|candidate| candidate.header != header);
+        candidates.insert(
+            0,
+            Candidate {
+                header,
+                chunks,
+                image,
+            },
+        );
+
+        let mut best = png.to_vec();
+        for candidate in &candidates {
+            for &filter_strategy in &self.filter_strategies {
+                for &compression_level in &self.compression_levels {
+                    let mut writer = PngWriter::new(HeaderInfo::new(&candidate.header)?);
+                    writer.chunks = candidate.chunks.clone();
+                    writer.filter_strategy = filter_strategy;
+                    writer.compression_level = compression_level;
+                    let encoded = writer.encode(&candidate.image)?;
+                    if encoded.len() < best.len() && Self::decode_pixels(&encoded)? == pixels {
+                        best = encoded;
+                    }
+                }
+            }
+        }
+        Ok(best)
+    }
+
+    /// Reductions replace PLTE and tRNS, so they are skipped when other
+    /// chunks tie the image to its current format.
+    fn is_reducible(header: &HeaderInfo, chunks: &[Chunk]) -> bool {
+        chunks.iter().all(|chunk| {
+            // A suggested palette in a truecolor image would be lost.
+            let suggested_palette =
+                chunk.chunk_type == ChunkType::PLTE && !header.color_type.is_indexed();
+            !suggested_palette && !FORMAT_DEPENDENT.contains(&chunk.chunk_type)
+        })
+    }
+
+    /// Builds the smaller formats that can hold `pixels` exactly.
+    fn reduced_candidates(
+        pixels: &[Pixel],
+        header: &HeaderInfo,
+        chunks: &[Chunk],
+    ) -> Vec<Candidate> {
+        let opaque = pixels.iter().all(|pixel| pixel[3] == 0xFFFF);
+        let gray = pixels
+            .iter()
+            .all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2]);
+        let eight_bit = pixels.iter().flatten().all(|&sample| sample % 257 == 0);
+        // An ICC profile is either for gray or for color images.
+        let has_iccp = chunks
+            .iter()
+            .any(|chunk| chunk.chunk_type == ChunkType::iCCP);
+        let keeps_gray = |to_gray: bool| !has_iccp || to_gray == header.color_type.is_grayscale();
+        let base: Vec<Chunk> = chunks
+            .iter()
+            .filter(|chunk| !matches!(chunk.chunk_type, ChunkType::PLTE | ChunkType::tRNS))
+            .cloned()
+            .collect();
+        let depth_for = |eight_bit: bool| if eight_bit { 8 } else { 16 };
+        let mut candidates = Vec::new();
+
+        if gray && keeps_gray(true) {
+            if opaque {
+                // The smallest depth at which every gray level is exact.
+                let bit_depth = [1u8, 2, 4, 8, 16]
+                    .into_iter()
+                    .find(|&bits| {
+                        let step = 0xFFFF / ((1u32 << bits) - 1);
+                        pixels
+                            .iter()
+                            .all(|pixel| (pixel[0] as u32).is_multiple_of(step))
+                    })
+                    .unwrap_or(16);
+                let step = 0xFFFF / ((1u32 << bit_depth) - 1);
+                let samples = pixels
+                    .iter()
+                    .map(|pixel| (pixel[0] as u32 / step) as u16)
+                    .collect();
+                candidates.push(Self::candidate(
+                    header,
+                    ColorType::Grayscale,
+                    bit_depth,
+                    samples,
+                    base.clone(),
+                ));
+            } else {
+                let bit_depth = depth_for(eight_bit);
+                let samples = pixels
+                    .iter()
+                    .flat_map(|pixel| [pixel[0], pixel[3]])
+                    .map(|sample| Self::narrow(sample, bit_depth))
+                    .collect();
+                candidates.push(Self::candidate(
+                    header,
+                    ColorType::GrayscaleAlpha,
+                    bit_depth,
+                    samples,
+                    base.clone(),
+                ));
+            }
+        }
+
+        if keeps_gray(false) {
+            let bit_depth = depth_for(eight_bit);
+            let (color_type, channels) = if opaque {
+                (ColorType::Rgb, 3)
+            } else {
+                (ColorType::Rgba, 4)
+            };
+            let samples = pixels
+                .iter()
+                .flat_map(|pixel| pixel[..channels].to_vec())
+                .map(|sample| Self::narrow(sample, bit_depth))
+                .collect();
+            candidates.push(Self::candidate(
+                header,
+                color_type,
+                bit_depth,
+                samples,
+                base.clone(),
+            ));
+        }
+
+        let palette_colors = if eight_bit && keeps_gray(false) {
+            Self::distinct_colors(pixels, 256)
+        } else {
+            None
+        };
+        if let Some(mut colors) = palette_colors {
+            // Translucent entries go first so the tRNS chunk stays short.
+            colors.sort_by_key(|pixel| pixel[3] == 0xFFFF);
+            let bit_depth = [1u8, 2, 4, 8]
+                .into_iter()
+                .find(|&bits| colors.len() <= 1 << bits)
+                .unwrap_or(8);
+            let samples = pixels
+                .iter()
+                .map(|pixel| colors.iter().position(|color| color == pixel).unwrap() as u16)
+                .collect();
+            let mut chunks = base;
+            let palette = colors
+                .iter()
+                .flat_map(|color| color[..3].iter().map(|&sample| (sample / 257) as u8))
+                .collect();
+            chunks.push(Chunk::from_data(ChunkType::PLTE, palette));
+            let alphas: Vec<u8> = colors
+                .iter()
+                .take_while(|color| color[3] != 0xFFFF)
+                .map(|color| (color[3] / 257) as u8)
+                .collect();
+            if !alphas.is_empty() {
+                chunks.push(Chunk::from_data(ChunkType::tRNS, alphas));
+            }
+            candidates.push(Self::candidate(
+                header,
+                ColorType::Indexed,
+                bit_depth,
+                samples,
+                chunks,
+            ));
+        }
+        candidates
+    }
+
+    /// The distinct colors of `pixels` in order of appearance, or `None` if
+    /// there are more than `limit`.
+    fn distinct_colors(pixels: &[Pixel], limit: usize) -> Option<Vec<Pixel>> {
+        let mut colors = Vec::new();
+        let mut seen = HashSet::new();
+        for pixel in pixels {
+            if seen.insert(*pixel) {
+                colors.push(*pixel);
+                if colors.len() > limit {
+                    return None;
+                }
+            }
+        }
+        Some(colors)
+    }
+
+    fn candidate(
+        header: &HeaderInfo,
+        color_type: ColorType,
+        bit_depth: u8,
+        samples: Vec<u16>,
+        chunks: Vec<Chunk>,
+    ) -> Candidate {
+        let mut ihdr = [0; 13];
+        ihdr[..4].copy_from_slice(&header.width.to_be_bytes());
+        ihdr[4..8].copy_from_slice(&header.height.to_be_bytes());
+        ihdr[8] = bit_depth;
+        ihdr[9] = color_type.as_u8();
+        ihdr[12] = header.interlace_method.as_u8();
+        let row_samples = header.width as usize * color_type.channels();
+        Candidate {
+            header: ihdr,
+            chunks,
+            image: Self::pack_samples(&samples, row_samples, bit_depth),
+        }
+    }
+
+    /// Converts a 16-bit sample to `bit_depth`, which is 8 or 16.
+    fn narrow(sample: u16, bit_depth: u8) -> u16 {
+        if bit_depth == 8 {
+            sample / 257
+        } else {
+            sample
+        }
+    }
+
+    /// Packs samples into scanlines of `row_samples` samples each, padding
+    /// every scanline to a whole byte.
+    fn pack_samples(samples: &[u16], row_samples: usize, bit_depth: u8) -> Vec<u8> {
+        let bits = bit_depth as usize;
+        let mut data = Vec::new();
+        for row in samples.chunks(row_samples) {
+            match bits {
+                16 => row
+                    .iter()
+                    .for_each(|sample| data.extend_from_slice(&sample.to_be_bytes())),
+                8 => data.extend(row.iter().map(|&sample| sample as u8)),
+                _ => {
+                    let mut scanline = vec![0u8; (row.len() * bits).div_ceil(8)];
+                    for (index, &sample) in row.iter().enumerate() {
+                        let bit_offset = index * bits;
+                        scanline[bit_offset / 8] |= (sample as u8) << (8 - bits - bit_offset % 8);
+                    }
+                    data.extend_from_slice(&scanline);
+                }
+            }
+        }
+        data
+    }
+
+    fn decode_pixels(png: &[u8]) -> Result<Vec<Pixel>, PngError> {
+        let raw_png = PngReader::from_bytes(png).load_png()?;
+        let image = PngReader::from_bytes(png).image_data(&raw_png)?;
+        Self::expand_pixels(&image, &raw_png)
+    }
+
+    /// Converts unfiltered image data to 16-bit RGBA, resolving the palette
+    /// and tRNS.
+    fn expand_pixels(image: &[u8], raw_png: &RawPng) -> Result<Vec<Pixel>, PngError> {
+        let header = &raw_png.header;
+        let channels = header.channels();
+        let bit_depth = header.bit_depth.bits();
+        let width = header.width as usize;
+        let scale = 0xFFFF / ((1u32 << bit_depth) - 1) as u16;
+        // Decoded as `PngReader` does: a suggested palette and a malformed
+        // tRNS play no part in the pixels.
+        let palette = match header.color_type {
+            ColorType::Indexed => raw_png.palette()?,
+            _ => None,
+        };
+        let transparency = raw_png.transparency().ok().flatten();
+        let mut pixels = Vec::with_capacity(width * header.height as usize);
+        for scanline in image.chunks_exact(header.scanline_length()) {
+            for x in 0..width {
+                let sample =
+                    |channel: usize| read_sample(scanline, x * channels + channel, bit_depth);
+                let samples: Vec<u16> = (0..channels).map(sample).collect();
+                let alpha = transparency
+                    .as_ref()
+                    .map_or(0xFFFF, |t| t.alpha(&samples) as u16 * 257);
+                let pixel = match header.color_type {
+                    ColorType::Grayscale => {
+                        let gray = samples[0] * scale;
+                        [gray, gray, gray, alpha]
+                    }
+                    ColorType::Rgb => [
+                        samples[0] * scale,
+                        samples[1] * scale,
+                        samples[2] * scale,
+                        alpha,
+                    ],
+                    ColorType::Indexed => {
+                        let palette = palette.as_ref().ok_or(PngError::MissingPalette)?;
+                        let [r, g, b] = palette.get(samples[0] as u8)?;
+                        [r as u16 * 257, g as u16 * 257, b as u16 * 257, alpha]
+                    }
+                    ColorType::GrayscaleAlpha => {
+                        let gray = samples[0] * scale;
+                        [gray, gray, gray, samples[1] * scale]
+                    }
+                    ColorType::Rgba => [
+                        samples[0] * scale,
+                        samples[1] * scale,
+                        samples[2] * scale,
+                        samples[3] * scale,
+                    ],
+                };
+                pixels.push(pixel);
+            }
+        }
+        Ok(pixels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes `image` uncompressed, so any optimization shrinks it.
+    fn encode(ihdr: [u8; 13], chunks: Vec<Chunk>, image: &[u8]) -> Vec<u8> {
+        let mut writer = PngWriter::new(HeaderInfo::new(&ihdr).unwrap());
+        writer.chunks = chunks;
+        writer.filter_strategy = FilterStrategy::Fixed(0);
+        writer.compression_level = 0;
+        writer.encode(image).unwrap()
+    }
+
+    fn ihdr(
+        width: u32,
+        height: u32,
+        bit_depth: u8,
+        color_type: ColorType,
+        interlace: u8,
+    ) -> [u8; 13] {
+        let mut ihdr = [0; 13];
+        ihdr[..4].copy_from_slice(&width.to_be_bytes());
+        ihdr[4..8].copy_from_slice(&height.to_be_bytes());
+        ihdr[8] = bit_depth;
+        ihdr[9] = color_type.as_u8();
+        ihdr[12] = interlace;
+        ihdr
+    }
+
+    /// Optimizes `png` and checks the result is no larger and holds the same
+    /// pixels, returning its header.
+    fn optimize(png: &[u8]) -> HeaderInfo {
+        let optimized = Optimizer::new().optimize(png).unwrap();
+        assert!(optimized.len() <= png.len());
+        assert_eq!(
+            Optimizer::decode_pixels(&optimized).unwrap(),
+            Optimizer::decode_pixels(png).unwrap()
+        );
+        PngReader::from_bytes(&optimized).load_png().unwrap().header
+    }
+
+    #[test]
+    fn reduces_opaque_gray_rgba_to_low_bit_depth_grayscale() {
+        for interlace in [0, 1] {
+            let image: Vec<u8> = (0..13 * 7)
+                .flat_map(|i| {
+                    let gray = [0, 85, 170, 255][i % 4];
+                    [gray, gray, gray, 255]
+                })
+                .collect();
+            let png = encode(ihdr(13, 7, 8, ColorType::Rgba, interlace), vec![], &image);
+            let header = optimize(&png);
+            assert_eq!(header.color_type, ColorType::Grayscale);
+            assert_eq!(header.bit_depth.bits(), 2);
+        }
+    }
+
+    #[test]
+    fn reduces_few_translucent_colors_to_a_palette() {
+        let colors = [[255, 0, 0, 255], [0, 255, 0, 128], [0, 0, 255, 0]];
+        let mut state = 1u32;
+        let image: Vec<u8> = (0..16 * 16)
+            .flat_map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                colors[(state >> 16) as usize % 3]
+            })
+            .collect();
+        let png = encode(ihdr(16, 16, 8, ColorType::Rgba, 0), vec![], &image);
+        let header = optimize(&png);
+        assert_eq!(header.color_type, ColorType::Indexed);
+        assert_eq!(header.bit_depth.bits(), 2);
+    }
+
+    #[test]
+    fn narrows_sixteen_bit_samples_that_fit_in_eight_bits() {
+        let image: Vec<u8> = (0..8 * 8 * 3)
+            .flat_map(|i| ((i * 37 % 256) as u16 * 257).to_be_bytes())
+            .collect();
+        let png = encode(ihdr(8, 8, 16, ColorType::Rgb, 0), vec![], &image);
+        assert_eq!(optimize(&png).bit_depth.bits(), 8);
+    }
+
+    #[test]
+    fn keeps_the_format_of_full_depth_images() {
+        let image: Vec<u8> = (0..8 * 8)
+            .flat_map(|i: u32| (i.wrapping_mul(40_503) as u16).to_be_bytes())
+            .collect();
+        let png = encode(ihdr(8, 8, 16, ColorType::Grayscale, 0), vec![], &image);
+        let header = optimize(&png);
+        assert_eq!(header.color_type, ColorType::Grayscale);
+        assert_eq!(header.bit_depth.bits(), 16);
+    }
+
+    #[test]
+    fn does_not_reduce_images_with_format_dependent_chunks() {
+        let image = [10, 10, 10].repeat(16);
+        let background = Chunk::from_data(ChunkType::bKGD, vec![0; 6]);
+        let png = encode(ihdr(4, 4, 8, ColorType::Rgb, 0), vec![background], &image);
+        let header = optimize(&png);
+        assert_eq!(header.color_type, ColorType::Rgb);
+        assert_eq!(header.bit_depth.bits(), 8);
+    }
+
+    #[test]
+    fn returns_animated_images_unchanged() {
+        let animation = Chunk::from_data(ChunkType::acTL, vec![0, 0, 0, 1, 0, 0, 0, 0]);
+        let png = encode(ihdr(4, 4, 8, ColorType::Rgb, 0), vec![animation], &[0; 48]);
+        assert_eq!(Optimizer::new().optimize(&png).unwrap(), png);
+    }
+
+    #[test]
+    fn drops_malformed_chunks_the_decoder_ignores() {
+        let png = encode(ihdr(1, 1, 8, ColorType::Rgba, 0), vec![], &[1, 2, 3, 4]);
+        let transparency = Chunk::from_data(ChunkType::tRNS, vec![0; 6]);
+        let png = [&png[..33], &transparency.to_bytes(), &png[33..]].concat();
+        let optimized = Optimizer::new().optimize(&png).unwrap();
+        let raw_png = PngReader::from_bytes(&optimized).load_png().unwrap();
+        assert!(raw_png.find_chunk(ChunkType::tRNS).is_none());
+        assert_eq!(
+            Optimizer::decode_pixels(&optimized).unwrap(),
+            Optimizer::decode_pixels(&png).unwrap()
+        );
+    }
+
+    #[test]
+    fn drops_unknown_chunks_that_are_not_safe_to_copy() {
+        let unsafe_chunk = Chunk::from_data(ChunkType::from_bytes(*b"prVT"), vec![1]);
+        let safe_chunk = Chunk::from_data(ChunkType::from_bytes(*b"prVt"), vec![2]);
+        let image: Vec<u8> = (0..16).flat_map(|_| [9, 9, 9, 255]).collect();
+        let png = encode(
+            ihdr(4, 4, 8, ColorType::Rgba, 0),
+            vec![unsafe_chunk, safe_chunk.clone()],
+            &image,
+        );
+        let optimized = Optimizer::new().optimize(&png).unwrap();
+        let raw_png = PngReader::from_bytes(&optimized).load_png().unwrap();
+        assert!(raw_png
+            .find_chunk(ChunkType::from_bytes(*b"prVT"))
+            .is_none());
+        assert_eq!(
+            raw_png
+                .find_chunk(safe_chunk.chunk_type)
+                .map(|chunk| chunk.data()),
+            Some(&[2][..])
+        );
+    }
+
+    #[test]
+    fn returns_the_input_unchanged_only_when_nothing_is_smaller() {
+        let png = Optimizer::new()
+            .optimize(&encode(
+                ihdr(1, 1, 8, ColorType::Rgb, 0),
+                vec![],
+                &[7, 8, 9],
+            ))
+            .unwrap();
+        assert_eq!(Optimizer::new().optimize(&png).unwrap(), png);
+        // Dropping the trailing data makes the re-encoded file smaller.
+        let with_trailing_data = [&png[..], b"trailing"].concat();
+        assert_eq!(Optimizer::new().optimize(&with_trailing_data).unwrap(), png);
+    }
+}
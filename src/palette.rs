@@ -0,0 +1,39 @@
+// palette.rs
+use crate::error::PngError;
+
+#[derive(Debug, Clone)]
+pub struct Palette {
+    pub entries: Vec<[u8; 3]>,
+}
+
+impl Palette {
+    /// Parses the contents of a PLTE chunk, which must hold between 1 and 256
+    /// RGB triples.
+    pub fn new(data: &[u8]) -> Result<Palette, PngError> {
+        if data.is_empty() || !data.len().is_multiple_of(3) || data.len() / 3 > 256 {
+            return Err(PngError::InvalidPaletteLength(data.len()));
+        }
+
+        let entries = data
+            .chunks_exact(3)
+            .map(|entry| [entry[0], entry[1], entry[2]])
+            .collect();
+
+        Ok(Palette { entries })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, index: u8) -> Result<[u8; 3], PngError> {
+        self.entries
+            .get(index as usize)
+            .copied()
+            .ok_or(PngError::PaletteIndexOutOfRange(index, self.entries.len()))
+    }
+}
@@ -0,0 +1,106 @@
+// physical.rs
+use std::fmt;
+
+use crate::chunk::{Chunk, ChunkType};
+use crate::error::PngError;
+
+const METERS_PER_INCH: f64 = 0.0254;
+
+/// Unit specifier of a pHYs chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhysicalUnit {
+    /// Only the pixel aspect ratio is known.
+    Unknown,
+    Meter,
+}
+
+impl PhysicalUnit {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(PhysicalUnit::Unknown),
+            1 => Some(PhysicalUnit::Meter),
+            _ => None,
+        }
+    }
+
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            PhysicalUnit::Unknown => 0,
+            PhysicalUnit::Meter => 1,
+        }
+    }
+}
+
+/// Intended pixel size or aspect ratio, from a pHYs chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhysicalDimensions {
+    pub pixels_per_unit_x: u32,
+    pub pixels_per_unit_y: u32,
+    pub unit: PhysicalUnit,
+}
+
+impl PhysicalDimensions {
+    pub fn new(data: &[u8]) -> Result<PhysicalDimensions, PngError> {
+        if data.len() != 9 {
+            return Err(PngError::InvalidChunkLength(ChunkType::pHYs, data.len()));
+        }
+        let unit = PhysicalUnit::from_u8(data[8]).ok_or_else(|| {
+            PngError::InvalidChunkData(
+                ChunkType::pHYs,
+                format!("unknown unit specifier {}", data[8]),
+            )
+        })?;
+        Ok(PhysicalDimensions {
+            pixels_per_unit_x: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+            pixels_per_unit_y: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            unit,
+        })
+    }
+
+    /// Dimensions for a resolution in dots per inch, rounded to whole pixels
+    /// per meter as pHYs requires.
+    pub fn from_dpi(dpi_x: f64, dpi_y: f64) -> PhysicalDimensions {
+        let to_meters = |dpi: f64| (dpi / METERS_PER_INCH).round() as u32;
+        PhysicalDimensions {
+            pixels_per_unit_x: to_meters(dpi_x),
+            pixels_per_unit_y: to_meters(dpi_y),
+            unit: PhysicalUnit::Meter,
+        }
+    }
+
+    /// Resolution in dots per inch, if the unit is known.
+    pub fn dpi(&self) -> Option<(f64, f64)> {
+        match self.unit {
+            PhysicalUnit::Meter => Some((
+                self.pixels_per_unit_x as f64 * METERS_PER_INCH,
+                self.pixels_per_unit_y as f64 * METERS_PER_INCH,
+            )),
+            PhysicalUnit::Unknown => None,
+        }
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = Vec::with_capacity(9);
+        data.extend_from_slice(&self.pixels_per_unit_x.to_be_bytes());
+        data.extend_from_slice(&self.pixels_per_unit_y.to_be_bytes());
+        data.push(self.unit.as_u8());
+        Chunk::from_data(ChunkType::pHYs, data)
+    }
+}
+
+impl fmt::Display for PhysicalDimensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.dpi() {
+            Some((dpi_x, dpi_y)) => write!(
+                f,
+                "{}x{} pixels per meter ({:.0}x{:.0} DPI)",
+                self.pixels_per_unit_x, self.pixels_per_unit_y, dpi_x, dpi_y
+            ),
+            None => write!(
+                f,
+                "Aspect ratio {}:{}",
+                self.pixels_per_unit_x, self.pixels_per_unit_y
+            ),
+        }
+    }
+}
@@ -3,114 +3,209 @@ use std::convert::TryInto;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
+use std::io::{BufReader, SeekFrom};
 
 use flate2::read::ZlibDecoder;
 
-use crate::chunk::{Chunk, ChunkType};
+use crate::chunk::ChunkType;
+use crate::chunk_iter::ChunkIter;
+use crate::color::{GammaTable, SRGB_GAMMA};
 use crate::error::PngError;
-use crate::header::HeaderInfo;
+use crate::filter;
+use crate::header::{ColorType, HeaderInfo, InterlaceMethod};
 use crate::image_type::{Brightness, VisualData, PNG};
+use crate::palette::Palette;
 use crate::raw_data::RawPng;
+use crate::row_decoder::RowDecoder;
+use crate::transparency::Transparency;
 
-pub struct PngReader {
-    file_path: String,
+/// Adam7 passes as (x start, y start, x step, y step).
+pub const ADAM7_PASSES: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// Reads PNG data from any [`Read`] source, such as a file, an in-memory
+/// buffer or a network body.
+pub struct PngReader<R> {
+    reader: R,
+    /// Expected number of bytes left in the source, used to size the read buffer.
+    length_hint: usize,
+    /// Display gamma to correct decoded colors for, if any.
+    display_gamma: Option<f64>,
 }
 
-impl PngReader {
+impl PngReader<BufReader<File>> {
     pub fn new(file_path: &str) -> io::Result<Self> {
+        let file = File::open(file_path)?;
+        Self::from_seekable(BufReader::new(file))
+    }
+}
+
+impl<'a> PngReader<&'a [u8]> {
+    pub fn from_bytes(bytes: &'a [u8]) -> Self {
+        Self {
+            reader: bytes,
+            length_hint: bytes.len(),
+            display_gamma: None,
+        }
+    }
+}
+
+impl<R: Read + Seek> PngReader<R> {
+    /// Creates a reader for a seekable source, reading from its current
+    /// position. The remaining length is measured up front so the data can be
+    /// read into a single allocation.
+    pub fn from_seekable(mut reader: R) -> io::Result<Self> {
+        let position = reader.stream_position()?;
+        let end = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(position))?;
         Ok(Self {
-            file_path: file_path.to_string(),
+            reader,
+            length_hint: end.saturating_sub(position) as usize,
+            display_gamma: None,
         })
     }
+}
+
+impl<R: Read> PngReader<R> {
+    pub fn from_reader(reader: R) -> Self {
+        Self {
+            reader,
+            length_hint: 0,
+            display_gamma: None,
+        }
+    }
+
+    /// Enables gamma correction of decoded colors for a display with the given
+    /// gamma, 2.2 for typical monitors, or disables it with `None`. The image
+    /// gamma comes from gAMA; images tagged sRGB, or without a usable gAMA,
+    /// are treated as sRGB. ICC profiles and cICP are not interpreted. Alpha
+    /// is never corrected.
+    pub fn set_display_gamma(&mut self, display_gamma: Option<f64>) -> Result<(), PngError> {
+        if let Some(gamma) = display_gamma.filter(|gamma| !gamma.is_finite() || *gamma <= 0.0) {
+            return Err(PngError::InvalidDisplayGamma(gamma));
+        }
+        self.display_gamma = display_gamma;
+        Ok(())
+    }
 
     pub fn load_png(&mut self) -> Result<RawPng, PngError> {
-        let buffer = self.read_file()?;
+        let buffer = self.read_all()?;
         self.png_chunk_from_buffer(&buffer)
     }
 
-    fn read_file(&self) -> Result<Vec<u8>, PngError> {
-        let mut file = File::open(&self.file_path)?;
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer)?;
+    fn read_all(&mut self) -> Result<Vec<u8>, PngError> {
+        let mut buffer = Vec::with_capacity(self.length_hint);
+        self.reader.read_to_end(&mut buffer)?;
         Ok(buffer)
     }
 
+    /// Consumes the reader and returns a lazy iterator over its chunks,
+    /// starting with IHDR.
+    pub fn chunks(self) -> Result<ChunkIter<R>, PngError> {
+        ChunkIter::new(self.reader)
+    }
+
+    /// Consumes the reader and returns a decoder yielding one unfiltered
+    /// scanline at a time. Interlaced images are not supported.
+    pub fn rows(self) -> Result<RowDecoder<R>, PngError> {
+        RowDecoder::new(self.chunks()?)
+    }
+
     fn png_chunk_from_buffer(&self, buffer: &[u8]) -> Result<RawPng, PngError> {
-        let header_slice = &buffer[0..8];
-        let mut offset = 8;
+        let mut chunk_iter = ChunkIter::new(buffer)?;
+        let signature: [u8; 8] = buffer[..8].try_into().unwrap();
         let mut chunks = Vec::new();
 
         // Read the IHDR chunk
-        let ihdr_length = u32::from_be_bytes([
-            buffer[offset],
-            buffer[offset + 1],
-            buffer[offset + 2],
-            buffer[offset + 3],
-        ]) as usize;
-        let ihdr_data = &buffer[offset + 8..offset + 8 + ihdr_length];
-        let header_info =
-            HeaderInfo::new(ihdr_data).map_err(|e| PngError::ParseError(e.to_string()))?;
-        offset += 8 + ihdr_length + 4;
-
-        // Read the remaining chunks
-        while offset < buffer.len() {
-            let length = u32::from_be_bytes([
-                buffer[offset],
-                buffer[offset + 1],
-                buffer[offset + 2],
-                buffer[offset + 3],
-            ]) as usize;
-            let chunk_type = ChunkType::from_bytes([
-                buffer[offset + 4],
-                buffer[offset + 5],
-                buffer[offset + 6],
-                buffer[offset + 7],
-            ]);
-            // let chunk_type = String::from_utf8_lossy(&buffer[offset + 4..offset + 8]).to_string();
-            let data = buffer[offset + 8..offset + 8 + length].to_vec();
-            let crc = u32::from_be_bytes([
-                buffer[offset + 8 + length],
-                buffer[offset + 8 + length + 1],
-                buffer[offset + 8 + length + 2],
-                buffer[offset + 8 + length + 3],
-            ]);
-            let chunk = Chunk {
-                length: length as u32,
-                chunk_type,
-                data,
-                crc,
-            };
-            chunks.push(chunk);
-            offset += 8 + length + 4;
+        let ihdr = chunk_iter
+            .next()
+            .unwrap_or(Err(PngError::TruncatedChunk(8)))?;
+        let header_info = HeaderInfo::from_chunk(ihdr)?;
+
+        // Read the remaining chunks, which end with IEND
+        for chunk in &mut chunk_iter {
+            chunks.push(chunk?);
         }
-        let signature: [u8; 8] = header_slice.try_into().unwrap();
-        let raw_png = RawPng::new(signature, header_info, chunks)?;
+        let mut raw_png = RawPng::new(signature, header_info, chunks)?;
+        raw_png.trailing_data = chunk_iter.into_inner().to_vec();
         Ok(raw_png)
     }
 
-    pub fn to_brightness_data(
-        &self,
-        raw_png: &RawPng,
-        step_size: usize,
-    ) -> io::Result<Option<VisualData>> {
-        let required_chunk_type = ChunkType::IDAT;
+    /// Decompresses and unfilters the IDAT data of `raw_png`, returning packed
+    /// scanlines without filter type bytes, deinterlaced if needed.
+    pub fn image_data(&self, raw_png: &RawPng) -> Result<Vec<u8>, PngError> {
         let mut idat_data = Vec::new();
         for chunk in &raw_png.chunks {
-            if chunk.chunk_type.is_same_as(&required_chunk_type) && chunk.is_valid() {
-                idat_data.extend_from_slice(&chunk.data);
+            if chunk.chunk_type.is_same_as(&ChunkType::IDAT) {
+                chunk.validate()?;
+                idat_data.extend_from_slice(chunk.data());
             }
         }
 
-        let decompressed_data = PngReader::decompress_data(&idat_data)?;
-        let unfiltered_data = PngReader::unfilter_data(&decompressed_data, &raw_png.header)?;
-        let visual_data = PngReader::convert_to_visual_code(&unfiltered_data, &raw_png.header)?;
-        let visual_data_result = PngReader::reshape_data(&visual_data, &raw_png.header, step_size)?;
+        let decompressed_data =
+            Self::decompress_data(&idat_data).map_err(|e| PngError::ParseError(e.to_string()))?;
+        Self::unfilter_data(&decompressed_data, &raw_png.header)
+            .map_err(|e| PngError::ParseError(e.to_string()))
+    }
 
-        let brightness_data =
-            PngReader::brightness_representation(visual_data_result.unwrap(), &raw_png.header)?;
+    pub fn to_brightness_data(
+        &self,
+        raw_png: &RawPng,
+        step_size: usize,
+    ) -> Result<Option<VisualData>, PngError> {
+        let unfiltered_data = self.image_data(raw_png)?;
+        let gamma_table = self.gamma_table(raw_png);
+        // Only indexed images need the palette. A malformed tRNS is ignored,
+        // as libpng does, rather than failing the decode.
+        let palette = match raw_png.header.color_type {
+            ColorType::Indexed => raw_png.palette()?,
+            _ => None,
+        };
+        let transparency = raw_png.transparency().ok().flatten();
+        let visual_data = Self::convert_to_visual_code(
+            &unfiltered_data,
+            &raw_png.header,
+            palette.as_ref(),
+            transparency.as_ref(),
+            gamma_table.as_ref(),
+        )?;
+        let visual_data_result = Self::reshape_data(&visual_data, &raw_png.header, step_size)
+            .map_err(|e| PngError::ParseError(e.to_string()))?;
+
+        let brightness_data = Self::brightness_representation(visual_data_result.unwrap())
+            .map_err(|e| PngError::ParseError(e.to_string()))?;
         Ok(brightness_data)
     }
 
+    /// Builds the lookup table for the configured display gamma. Palette
+    /// entries are always 8-bit, whatever the bit depth of the indices.
+    fn gamma_table(&self, raw_png: &RawPng) -> Option<GammaTable> {
+        let display_gamma = self.display_gamma?;
+        // sRGB overrides gAMA, which in turn is the fallback for decoders
+        // that do not handle ICC profiles. A malformed gAMA is ignored rather
+        // than failing the decode.
+        let file_gamma = match raw_png.find_chunk(ChunkType::sRGB) {
+            Some(_) => SRGB_GAMMA,
+            None => raw_png.gamma().ok().flatten().unwrap_or(SRGB_GAMMA),
+        };
+        let bit_depth = match raw_png.header.color_type {
+            ColorType::Indexed => 8,
+            _ => raw_png.header.bit_depth.bits(),
+        };
+        Some(GammaTable::new(
+            file_gamma.value(),
+            display_gamma,
+            bit_depth,
+        ))
+    }
+
     fn decompress_data(data: &[u8]) -> io::Result<Vec<u8>> {
         let mut decoder = ZlibDecoder::new(data);
         let mut decompressed_data = Vec::new();
@@ -119,124 +214,181 @@ impl PngReader {
     }
 
     fn unfilter_data(data: &[u8], header: &HeaderInfo) -> io::Result<Vec<u8>> {
-        let bytes_per_pixel = match header.color_type {
-            0 => 1,
-            2 => 3,
-            3 => 1,
-            4 => 2,
-            6 => 4,
-            _ => {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "Invalid color type",
-                ))
-            }
-        };
-        let scanline_length = (header.width * bytes_per_pixel as u32) as usize;
-        let mut unfiltered_data = Vec::new();
-        // let mut previous_scanline = vec![0; scanline_length];
-        let mut offset = 0;
-        while offset < data.len() {
-            let filter_type = data[offset];
-            let scanline = &data[offset + 1..offset + 1 + scanline_length];
-            let unfiltered_scanline = match filter_type {
-                0 => PngReader::unfilter_none(scanline)?,
-                _ => {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Invalid filter type or not implemented yet",
-                    ))
-                }
-            };
+        // Check the data covers the whole image before sizing any buffer from
+        // the header, which may claim far more than the file holds.
+        let image_length = (header.height as usize).checked_mul(1 + header.scanline_length());
+        if image_length.is_none_or(|length| data.len() < length) {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Truncated image data",
+            ));
+        }
+        match header.interlace_method {
+            InterlaceMethod::None => Self::unfilter_scanlines(
+                data,
+                header,
+                header.width as usize,
+                header.height as usize,
+            ),
+            InterlaceMethod::Adam7 => Self::deinterlace_data(data, header),
+        }
+    }
+
+    /// Unfilters `height` scanlines of `width` pixels, the full image size or
+    /// that of an Adam7 pass. Data after the last scanline is ignored.
+    fn unfilter_scanlines(
+        data: &[u8],
+        header: &HeaderInfo,
+        width: usize,
+        height: usize,
+    ) -> io::Result<Vec<u8>> {
+        let bytes_per_pixel = header.bytes_per_pixel();
+        let scanline_length = header.pass_scanline_length(width);
+        if data.len() < height * (1 + scanline_length) {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Truncated scanline in image data",
+            ));
+        }
+        let mut unfiltered_data = Vec::with_capacity(height * scanline_length);
+        let mut previous_scanline = vec![0; scanline_length];
+        for row in data.chunks_exact(1 + scanline_length).take(height) {
+            let unfiltered_scanline =
+                filter::unfilter_scanline(row[0], &row[1..], &previous_scanline, bytes_per_pixel)?;
             unfiltered_data.extend_from_slice(&unfiltered_scanline);
-            // previous_scanline = unfiltered_scanline;
-            offset += 1 + scanline_length;
+            previous_scanline = unfiltered_scanline;
         }
         Ok(unfiltered_data)
     }
 
-    fn convert_to_visual_code(data: &[u8], header: &HeaderInfo) -> io::Result<Vec<u8>> {
-        let bytes_per_pixel = match header.color_type {
-            0 => 1,
-            2 => 3,
-            3 => 1,
-            4 => 2,
-            6 => 4,
-            _ => {
+    /// Splits Adam7 data into its seven reduced images, unfilters each pass on
+    /// its own and scatters the pixels into a progressive full-size buffer.
+    fn deinterlace_data(data: &[u8], header: &HeaderInfo) -> io::Result<Vec<u8>> {
+        let width = header.width as usize;
+        let height = header.height as usize;
+        let bits_per_pixel = header.bits_per_pixel();
+        let scanline_length = header.scanline_length();
+        let mut image = vec![0; scanline_length * height];
+        let mut offset = 0;
+        for &(x_start, y_start, x_step, y_step) in ADAM7_PASSES.iter() {
+            // Passes that fall entirely outside small images contribute no bytes.
+            let pass_width = width.saturating_sub(x_start).div_ceil(x_step);
+            let pass_height = height.saturating_sub(y_start).div_ceil(y_step);
+            if pass_width == 0 || pass_height == 0 {
+                continue;
+            }
+            let pass_scanline_length = header.pass_scanline_length(pass_width);
+            let pass_length = pass_height * (1 + pass_scanline_length);
+            if offset + pass_length > data.len() {
                 return Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "Invalid color type",
-                ))
+                    io::ErrorKind::UnexpectedEof,
+                    "Truncated Adam7 pass in image data",
+                ));
             }
-        };
-        let mut rgb_data = Vec::new();
-        let mut offset = 0;
-        while offset < data.len() {
-            let pixel = match header.color_type {
-                0 => {
-                    let gray = data[offset];
-                    vec![gray, gray, gray, 255]
-                }
-                2 => {
-                    let r = data[offset];
-                    let g = data[offset + 1];
-                    let b = data[offset + 2];
-                    vec![r, g, b, 255]
-                }
-                3 => {
-                    let palette_index = data[offset];
-                    let palette_offset = palette_index as usize * 3;
-                    let r = data[palette_offset];
-                    let g = data[palette_offset + 1];
-                    let b = data[palette_offset + 2];
-                    vec![r, g, b, 255]
-                }
-                4 => {
-                    let gray = data[offset];
-                    let alpha = data[offset + 1];
-                    vec![gray, gray, gray, alpha]
-                }
-                6 => {
-                    let r = data[offset];
-                    let g = data[offset + 1];
-                    let b = data[offset + 2];
-                    let alpha = data[offset + 3];
-                    vec![r, g, b, alpha]
+            let pass = Self::unfilter_scanlines(
+                &data[offset..offset + pass_length],
+                header,
+                pass_width,
+                pass_height,
+            )?;
+            for (pass_y, pass_row) in pass.chunks_exact(pass_scanline_length).enumerate() {
+                let row_start = (y_start + pass_y * y_step) * scanline_length;
+                let row = &mut image[row_start..row_start + scanline_length];
+                for pass_x in 0..pass_width {
+                    let x = x_start + pass_x * x_step;
+                    if bits_per_pixel >= 8 {
+                        let bytes = bits_per_pixel / 8;
+                        row[x * bytes..(x + 1) * bytes]
+                            .copy_from_slice(&pass_row[pass_x * bytes..(pass_x + 1) * bytes]);
+                    } else {
+                        // Sub-byte pixels are always a single sample.
+                        let value = read_sample(pass_row, pass_x, bits_per_pixel) as u8;
+                        let bit_offset = x * bits_per_pixel;
+                        let shift = 8 - bits_per_pixel - bit_offset % 8;
+                        row[bit_offset / 8] |= value << shift;
+                    }
                 }
-                _ => {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Invalid color type",
-                    ))
-                }
-            };
-            rgb_data.extend_from_slice(&pixel);
-            offset += bytes_per_pixel;
+            }
+            offset += pass_length;
+        }
+        Ok(image)
+    }
+
+    fn convert_to_visual_code(
+        data: &[u8],
+        header: &HeaderInfo,
+        palette: Option<&Palette>,
+        transparency: Option<&Transparency>,
+        gamma_table: Option<&GammaTable>,
+    ) -> Result<Vec<u8>, PngError> {
+        let channels = header.channels();
+        let bit_depth = header.bit_depth.bits();
+        let width = header.width as usize;
+        let scanline_length = header.scanline_length();
+        let mut rgb_data = Vec::with_capacity(data.len() / scanline_length * width * 4);
+        // Each scanline may end with padding bits, so pixels are located per row.
+        for scanline in data.chunks_exact(scanline_length) {
+            for x in 0..width {
+                let sample =
+                    |channel: usize| read_sample(scanline, x * channels + channel, bit_depth);
+                let scaled = |channel: usize| Self::scale_to_u8(sample(channel), bit_depth);
+                // Color channels go through the gamma table; alpha is linear.
+                let color = |channel: usize| match gamma_table {
+                    Some(table) => table.correct(sample(channel)),
+                    None => scaled(channel),
+                };
+                // tRNS keys are compared against the raw samples, before any scaling.
+                let alpha = |samples: &[u16]| transparency.map_or(255, |t| t.alpha(samples));
+                let pixel = match header.color_type {
+                    ColorType::Grayscale => {
+                        let gray = color(0);
+                        [gray, gray, gray, alpha(&[sample(0)])]
+                    }
+                    ColorType::Rgb => [
+                        color(0),
+                        color(1),
+                        color(2),
+                        alpha(&[sample(0), sample(1), sample(2)]),
+                    ],
+                    ColorType::Indexed => {
+                        let palette_index = sample(0) as u8;
+                        let palette = palette.ok_or(PngError::MissingPalette)?;
+                        let [r, g, b] = palette.get(palette_index)?;
+                        let [r, g, b] = match gamma_table {
+                            Some(table) => [r, g, b].map(|value| table.correct(value as u16)),
+                            None => [r, g, b],
+                        };
+                        [r, g, b, alpha(&[palette_index as u16])]
+                    }
+                    ColorType::GrayscaleAlpha => {
+                        let gray = color(0);
+                        [gray, gray, gray, scaled(1)]
+                    }
+                    ColorType::Rgba => [color(0), color(1), color(2), scaled(3)],
+                };
+                rgb_data.extend_from_slice(&pixel);
+            }
         }
         Ok(rgb_data)
     }
 
-    fn unfilter_none(scanline: &[u8]) -> io::Result<Vec<u8>> {
-        Ok(scanline.to_vec())
+    /// Rescales a sample to the 0..=255 range. 16-bit samples are rounded down
+    /// to 8 bits and sub-byte samples are stretched to the full range.
+    fn scale_to_u8(sample: u16, bit_depth: usize) -> u8 {
+        match bit_depth {
+            16 => ((sample as u32 * 255 + 32767) / 65535) as u8,
+            8 => sample as u8,
+            _ => (sample as u32 * 255 / ((1 << bit_depth) - 1)) as u8,
+        }
     }
+
     fn reshape_data(
         data: &[u8],
         header: &HeaderInfo,
         step_size: usize,
     ) -> io::Result<Option<VisualData>> {
-        let bytes_per_pixel = match header.color_type {
-            0 => 1,
-            2 => 3,
-            3 => 1,
-            4 => 2,
-            6 => 4,
-            _ => {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "Invalid color type",
-                ))
-            }
-        };
+        // The data comes from `convert_to_visual_code`, which always emits RGBA.
+        let bytes_per_pixel = 4;
         let mut reshaped_data = Vec::new();
         let width = header.width as usize;
         let height = header.height as usize;
@@ -250,77 +402,101 @@ impl PngReader {
         }
         let image = PNG {
             shape: (
-                header.width / step_size as u32,
-                header.height / step_size as u32,
+                header.width.div_ceil(step_size as u32),
+                header.height.div_ceil(step_size as u32),
             ),
             data: reshaped_data,
         };
         Ok(Some(VisualData::RGBA(image)))
     }
 
-    fn brightness_representation(
-        b_data: VisualData,
-        header: &HeaderInfo,
-    ) -> io::Result<Option<VisualData>> {
-        let bytes_per_pixel = match header.color_type {
-            0 => 1,
-            2 => 3,
-            3 => 1,
-            4 => 2,
-            6 => 4,
+    fn brightness_representation(b_data: VisualData) -> io::Result<Option<VisualData>> {
+        let shape = match &b_data {
+            VisualData::RGBA(png) => png.shape,
             _ => {
                 return Err(io::Error::new(
                     io::ErrorKind::InvalidData,
-                    "Invalid color type",
+                    "Brightness can only be computed from RGBA data",
                 ));
             }
         };
         let data: Vec<u8> = (&b_data).into();
-        let mut _brightness_data = Vec::new();
-        let mut offset = 0;
-        while offset < data.len() {
-            let brightness = match header.color_type {
-                0 => {
-                    let gray = data[offset] as f32;
-                    gray
-                }
-                2 => {
-                    let r = data[offset] as f32;
-                    let g = data[offset + 1] as f32;
-                    let b = data[offset + 2] as f32;
-                    let gray = 0.299 * r + 0.587 * g + 0.114 * b;
-                    gray
-                }
-                3 => {
-                    let palette_index = data[offset];
-                    let gray = palette_index as f32;
-                    gray
-                }
-                4 => {
-                    let gray = data[offset] as f32;
-                    gray
-                }
-                6 => {
-                    let r = data[offset] as f32;
-                    let g = data[offset + 1] as f32;
-                    let b = data[offset + 2] as f32;
-                    let gray = 0.299 * r + 0.587 * g + 0.114 * b;
-                    gray
-                }
-                _ => {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Invalid color type",
-                    ));
-                }
-            };
-            _brightness_data.push(brightness);
-            offset += bytes_per_pixel;
+        let mut _brightness_data = Vec::with_capacity(data.len() / 4);
+        for pixel in data.chunks_exact(4) {
+            let r = pixel[0] as f32;
+            let g = pixel[1] as f32;
+            let b = pixel[2] as f32;
+            _brightness_data.push(0.299 * r + 0.587 * g + 0.114 * b);
         }
         let brightness_data = Brightness {
-            shape: (header.width, header.height),
+            shape,
             data: _brightness_data,
         };
         Ok(Some(VisualData::Brightness(brightness_data)))
     }
 }
+
+/// Reads sample `index` of a packed scanline.
+pub fn read_sample(scanline: &[u8], index: usize, bit_depth: usize) -> u16 {
+    match bit_depth {
+        16 => u16::from_be_bytes([scanline[index * 2], scanline[index * 2 + 1]]),
+        8 => scanline[index] as u16,
+        _ => {
+            let bit_offset = index * bit_depth;
+            let shift = 8 - bit_depth - bit_offset % 8;
+            let mask = (1u8 << bit_depth) - 1;
+            ((scanline[bit_offset / 8] >> shift) & mask) as u16
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::writer::PngWriter;
+
+    #[test]
+    fn ignores_data_after_the_last_scanline() {
+        let header = HeaderInfo::new(&[0, 0, 0, 2, 0, 0, 0, 2, 8, 0, 0, 0, 0]).unwrap();
+        let scanlines = [0, 1, 2, 0, 3, 4];
+        for surplus in [&[7][..], &[0, 9, 9], &[0, 9, 9, 1]] {
+            let data = [&scanlines[..], surplus].concat();
+            assert_eq!(
+                PngReader::<&[u8]>::unfilter_data(&data, &header).unwrap(),
+                [1, 2, 3, 4]
+            );
+        }
+        assert!(PngReader::<&[u8]>::unfilter_data(&scanlines[..5], &header).is_err());
+    }
+
+    #[test]
+    fn loads_and_decodes_images_with_malformed_ancillary_chunks() {
+        let rgba: [u8; 13] = [0, 0, 0, 1, 0, 0, 0, 1, 8, 6, 0, 0, 0];
+        let rgb: [u8; 13] = [0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0];
+        for (ihdr, chunk, pixel) in [
+            // tRNS is not allowed with an alpha channel.
+            (
+                rgba,
+                Chunk::from_data(ChunkType::tRNS, vec![0; 6]),
+                &[1, 2, 3, 4][..],
+            ),
+            // A suggested palette must hold whole entries.
+            (
+                rgb,
+                Chunk::from_data(ChunkType::PLTE, vec![0; 4]),
+                &[1, 2, 3],
+            ),
+        ] {
+            let png = PngWriter::new(HeaderInfo::new(&ihdr).unwrap())
+                .encode(pixel)
+                .unwrap();
+            // Insert the chunk straight after IHDR, where the writer would refuse it.
+            let png = [&png[..33], &chunk.to_bytes(), &png[33..]].concat();
+            let reader = PngReader::from_bytes(&png);
+            let raw_png = PngReader::from_bytes(&png).load_png().unwrap();
+            assert!(reader.to_brightness_data(&raw_png, 1).unwrap().is_some());
+            assert!(raw_png.to_bytes().is_err());
+        }
+    }
+}
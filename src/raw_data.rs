@@ -1,16 +1,68 @@
 // raw_png.rs
 use crate::chunk::Chunk;
-use crate::header::HeaderInfo;
+use crate::color::{Chromaticities, Cicp, ColorSpace, Gamma, IccProfile, RenderingIntent};
+use crate::header::{ColorType, HeaderInfo};
 use std::fmt;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufWriter;
 
 use crate::chunk::ChunkType;
-use crate::error::PngError;
+use crate::error::{PngError, SequenceViolation};
+use crate::palette::Palette;
+use crate::physical::PhysicalDimensions;
+use crate::text::{self, TextChunk, TEXT_CHUNKS};
+use crate::timestamp::ModificationTime;
+use crate::transparency::Transparency;
+
+/// The eight bytes every PNG file starts with.
+pub const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+/// Chunks that may appear more than once.
+const MULTIPLE_ALLOWED: [ChunkType; 7] = [
+    ChunkType::IDAT,
+    ChunkType::sPLT,
+    ChunkType::iTXt,
+    ChunkType::tEXt,
+    ChunkType::zTXt,
+    ChunkType::fcTL,
+    ChunkType::fdAT,
+];
+
+/// Chunks that must come before PLTE, and so before IDAT.
+const BEFORE_PLTE: [ChunkType; 8] = [
+    ChunkType::cHRM,
+    ChunkType::cICP,
+    ChunkType::cLLI,
+    ChunkType::gAMA,
+    ChunkType::iCCP,
+    ChunkType::mDCV,
+    ChunkType::sBIT,
+    ChunkType::sRGB,
+];
+
+/// Chunks that must come after PLTE and before IDAT.
+const AFTER_PLTE: [ChunkType; 3] = [ChunkType::bKGD, ChunkType::hIST, ChunkType::tRNS];
+
+/// Other chunks that must come before IDAT.
+const BEFORE_IDAT: [ChunkType; 8] = [
+    ChunkType::acTL,
+    ChunkType::eXIf,
+    ChunkType::oFFs,
+    ChunkType::pCAL,
+    ChunkType::pHYs,
+    ChunkType::sCAL,
+    ChunkType::sPLT,
+    ChunkType::sTER,
+];
 
 #[derive(Debug)]
 pub struct RawPng {
     pub signature: [u8; 8],
     pub header: HeaderInfo,
     pub chunks: Vec<Chunk>,
+    /// Bytes found after the IEND chunk.
+    pub trailing_data: Vec<u8>,
 }
 
 impl RawPng {
@@ -19,38 +71,312 @@ impl RawPng {
         header: HeaderInfo,
         chunks: Vec<Chunk>,
     ) -> Result<Self, PngError> {
-        if !RawPng::is_signature_valid(signature) {
-            return Err(PngError::InvalidSignature);
-        }
-        if !RawPng::verify_chunk_sequence(&chunks) {
-            return Err(PngError::InvalidChunkSequence);
-        }
-        Ok(Self {
+        let raw_png = Self {
             signature,
             header,
             chunks,
+            trailing_data: Vec::new(),
+        };
+        raw_png.validate_structure()?;
+        Ok(raw_png)
+    }
+
+    /// Checks the signature and the chunk order, which a file cannot be read
+    /// without. A malformed PLTE or tRNS is left for `palette` and
+    /// `transparency` to report, so it does not stop the image loading.
+    fn validate_structure(&self) -> Result<(), PngError> {
+        if !RawPng::is_signature_valid(self.signature) {
+            return Err(PngError::InvalidSignature);
+        }
+        let violations = RawPng::verify_chunk_sequence(&self.header, &self.chunks);
+        if !violations.is_empty() {
+            return Err(PngError::InvalidChunkSequence(violations));
+        }
+        Ok(())
+    }
+
+    /// Checks the signature, the chunk order, and the PLTE and tRNS chunks
+    /// against the header. Run before serializing, since every field can be
+    /// edited after loading.
+    pub fn validate(&self) -> Result<(), PngError> {
+        self.validate_structure()?;
+        self.palette()?;
+        self.transparency()?;
+        Ok(())
+    }
+
+    /// Serializes the file chunk by chunk without touching the image data:
+    /// signature, IHDR, the chunks in their current order and any trailing
+    /// data. An unmodified `RawPng` gives back the bytes it was loaded from,
+    /// including any bad CRCs. Chunks edited through `Chunk::set_data` carry
+    /// a recomputed length and CRC, and IHDR is rebuilt if the header fields
+    /// have been changed.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, PngError> {
+        self.validate()?;
+        let mut bytes = self.signature.to_vec();
+        bytes.extend_from_slice(&self.header_chunk()?.to_bytes());
+        for chunk in &self.chunks {
+            bytes.extend_from_slice(&chunk.to_bytes());
+        }
+        bytes.extend_from_slice(&self.trailing_data);
+        Ok(bytes)
+    }
+
+    /// The IHDR chunk as read, or one rebuilt from the header fields if they
+    /// no longer match it.
+    fn header_chunk(&self) -> Result<Chunk, PngError> {
+        let chunk = self.header.to_chunk();
+        if self.header.chunk.data() == chunk.data() {
+            return Ok(self.header.chunk.clone());
+        }
+        HeaderInfo::new(chunk.data())?;
+        Ok(chunk)
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<(), PngError> {
+        writer.write_all(&self.to_bytes()?).map_err(PngError::Io)
+    }
+
+    pub fn save(&self, file_path: &str) -> Result<(), PngError> {
+        let file = File::create(file_path).map_err(PngError::Io)?;
+        let mut writer = BufWriter::new(file);
+        self.write(&mut writer)?;
+        writer.flush().map_err(PngError::Io)
+    }
+
+    /// The first chunk of `chunk_type`, if any.
+    pub fn find_chunk(&self, chunk_type: ChunkType) -> Option<&Chunk> {
+        self.chunks
+            .iter()
+            .find(|chunk| chunk.chunk_type.is_same_as(&chunk_type))
+    }
+
+    pub fn palette(&self) -> Result<Option<Palette>, PngError> {
+        self.find_chunk(ChunkType::PLTE)
+            .map(|chunk| Palette::new(chunk.data()))
+            .transpose()
+    }
+
+    /// The tRNS chunk, checked against the color type and the palette size.
+    pub fn transparency(&self) -> Result<Option<Transparency>, PngError> {
+        let transparency = self
+            .find_chunk(ChunkType::tRNS)
+            .map(|chunk| Transparency::new(chunk.data(), self.header.color_type))
+            .transpose()?;
+        if let (Some(Transparency::Palette(alphas)), Some(palette)) =
+            (&transparency, self.palette()?)
+        {
+            if alphas.len() > palette.len() {
+                return Err(PngError::InvalidTransparency(
+                    self.header.color_type.as_u8(),
+                    alphas.len(),
+                ));
+            }
+        }
+        Ok(transparency)
+    }
+
+    pub fn gamma(&self) -> Result<Option<Gamma>, PngError> {
+        self.find_chunk(ChunkType::gAMA)
+            .map(|chunk| Gamma::new(chunk.data()))
+            .transpose()
+    }
+
+    pub fn chromaticities(&self) -> Result<Option<Chromaticities>, PngError> {
+        self.find_chunk(ChunkType::cHRM)
+            .map(|chunk| Chromaticities::new(chunk.data()))
+            .transpose()
+    }
+
+    pub fn srgb_intent(&self) -> Result<Option<RenderingIntent>, PngError> {
+        self.find_chunk(ChunkType::sRGB)
+            .map(|chunk| RenderingIntent::new(chunk.data()))
+            .transpose()
+    }
+
+    pub fn icc_profile(&self) -> Result<Option<IccProfile>, PngError> {
+        self.find_chunk(ChunkType::iCCP)
+            .map(|chunk| IccProfile::new(chunk.data()))
+            .transpose()
+    }
+
+    pub fn cicp(&self) -> Result<Option<Cicp>, PngError> {
+        self.find_chunk(ChunkType::cICP)
+            .map(|chunk| Cicp::new(chunk.data()))
+            .transpose()
+    }
+
+    /// Resolves the color space from the colorimetry chunks. cICP takes
+    /// precedence over iCCP, iCCP over sRGB, and sRGB over gAMA and cHRM.
+    pub fn color_space(&self) -> Result<ColorSpace, PngError> {
+        if let Some(cicp) = self.cicp()? {
+            return Ok(ColorSpace::Cicp(cicp));
+        }
+        if let Some(icc) = self.icc_profile()? {
+            return Ok(ColorSpace::Icc(icc));
+        }
+        if let Some(intent) = self.srgb_intent()? {
+            return Ok(ColorSpace::Srgb(intent));
+        }
+        let gamma = self.gamma()?;
+        let chromaticities = self.chromaticities()?;
+        if gamma.is_none() && chromaticities.is_none() {
+            return Ok(ColorSpace::Unspecified);
+        }
+        Ok(ColorSpace::Calibrated {
+            gamma,
+            chromaticities,
         })
     }
 
+    pub fn physical_dimensions(&self) -> Result<Option<PhysicalDimensions>, PngError> {
+        self.find_chunk(ChunkType::pHYs)
+            .map(|chunk| PhysicalDimensions::new(chunk.data()))
+            .transpose()
+    }
+
+    /// Replaces the pHYs chunk, which is placed before the first IDAT.
+    pub fn set_physical_dimensions(&mut self, dimensions: &PhysicalDimensions) {
+        self.chunks
+            .retain(|chunk| !chunk.chunk_type.is_same_as(&ChunkType::pHYs));
+        let index = self
+            .chunks
+            .iter()
+            .position(|chunk| chunk.chunk_type.is_same_as(&ChunkType::IDAT))
+            .unwrap_or(self.chunks.len());
+        self.chunks.insert(index, dimensions.to_chunk());
+    }
+
+    pub fn modification_time(&self) -> Result<Option<ModificationTime>, PngError> {
+        self.find_chunk(ChunkType::tIME)
+            .map(|chunk| ModificationTime::new(chunk.data()))
+            .transpose()
+    }
+
+    /// Replaces the tIME chunk, which is placed before IEND.
+    pub fn set_modification_time(&mut self, time: &ModificationTime) {
+        self.chunks
+            .retain(|chunk| !chunk.chunk_type.is_same_as(&ChunkType::tIME));
+        let index = self
+            .chunks
+            .iter()
+            .position(|chunk| chunk.chunk_type.is_same_as(&ChunkType::IEND))
+            .unwrap_or(self.chunks.len());
+        self.chunks.insert(index, time.to_chunk());
+    }
+
+    /// Parses every tEXt, zTXt and iTXt chunk, in file order.
+    pub fn text_entries(&self) -> Result<Vec<TextChunk>, PngError> {
+        self.chunks
+            .iter()
+            .filter(|chunk| TEXT_CHUNKS.contains(&chunk.chunk_type))
+            .map(TextChunk::from_chunk)
+            .collect()
+    }
+
+    /// Adds a text entry before IEND, replacing any entries with the same
+    /// keyword.
+    pub fn set_text(&mut self, entry: &TextChunk) -> Result<(), PngError> {
+        let chunk = entry.to_chunk()?;
+        text::remove_text(&mut self.chunks, entry.keyword());
+        let index = self
+            .chunks
+            .iter()
+            .position(|chunk| chunk.chunk_type.is_same_as(&ChunkType::IEND))
+            .unwrap_or(self.chunks.len());
+        self.chunks.insert(index, chunk);
+        Ok(())
+    }
+
+    /// Removes every text entry with `keyword`, returning whether any existed.
+    pub fn remove_text(&mut self, keyword: &str) -> bool {
+        text::remove_text(&mut self.chunks, keyword)
+    }
+
     pub fn is_signature_valid(signature: [u8; 8]) -> bool {
-        signature == [137, 80, 78, 71, 13, 10, 26, 10]
+        signature == SIGNATURE
     }
 
-    pub fn verify_chunk_sequence(chunks: &Vec<Chunk>) -> bool {
-        // let mut has_ihdr = false; // we deal ihdr in other part
-        let mut has_idat = false;
-        let mut has_iend = false;
+    /// Checks the chunks following IHDR against the PNG ordering rules and
+    /// returns every violation found.
+    pub fn verify_chunk_sequence(header: &HeaderInfo, chunks: &[Chunk]) -> Vec<SequenceViolation> {
+        let mut violations = Vec::new();
+        let position = |chunk_type: ChunkType| {
+            chunks
+                .iter()
+                .position(|chunk| chunk.chunk_type.is_same_as(&chunk_type))
+        };
+        let first_idat = position(ChunkType::IDAT);
+        let plte = position(ChunkType::PLTE);
+
+        if let Some(first_idat) = first_idat {
+            let idat_run = chunks[first_idat..]
+                .iter()
+                .take_while(|chunk| chunk.chunk_type.is_same_as(&ChunkType::IDAT))
+                .count();
+            if chunks[first_idat + idat_run..]
+                .iter()
+                .any(|chunk| chunk.chunk_type.is_same_as(&ChunkType::IDAT))
+            {
+                violations.push(SequenceViolation::NonConsecutiveIdat);
+            }
+        } else {
+            violations.push(SequenceViolation::MissingChunk(ChunkType::IDAT));
+        }
+
+        match position(ChunkType::IEND) {
+            Some(iend) if iend + 1 < chunks.len() => violations.push(
+                SequenceViolation::ChunkAfterIend(chunks[iend + 1].chunk_type),
+            ),
+            Some(_) => {}
+            None => violations.push(SequenceViolation::MissingChunk(ChunkType::IEND)),
+        }
+
+        match (header.color_type, plte) {
+            (ColorType::Indexed, None) => {
+                violations.push(SequenceViolation::MissingChunk(ChunkType::PLTE))
+            }
+            (ColorType::Grayscale | ColorType::GrayscaleAlpha, Some(_)) => {
+                violations.push(SequenceViolation::ForbiddenChunk(ChunkType::PLTE))
+            }
+            _ => {}
+        }
+
+        let mut seen: Vec<ChunkType> = Vec::new();
+        for (index, chunk) in chunks.iter().enumerate() {
+            let chunk_type = chunk.chunk_type;
+            // IHDR is held by the header, so any IHDR here is a repeat.
+            if seen.contains(&chunk_type) || chunk_type == ChunkType::IHDR {
+                if !MULTIPLE_ALLOWED.contains(&chunk_type)
+                    && !matches!(chunk_type, ChunkType::Other(_))
+                    && !violations.contains(&SequenceViolation::DuplicateChunk(chunk_type))
+                {
+                    violations.push(SequenceViolation::DuplicateChunk(chunk_type));
+                }
+            } else {
+                seen.push(chunk_type);
+            }
 
-        for chunk in chunks {
-            match chunk.chunk_type {
-                // ChunkType::IHDR => has_ihdr = true,// we deal ihdr in other part
-                ChunkType::IDAT => has_idat = true,
-                ChunkType::IEND => has_iend = true,
-                _ => {}
+            if let Some(plte) = plte {
+                if BEFORE_PLTE.contains(&chunk_type) && index > plte {
+                    violations.push(SequenceViolation::MustPrecede(chunk_type, ChunkType::PLTE));
+                }
+                if AFTER_PLTE.contains(&chunk_type) && index < plte {
+                    violations.push(SequenceViolation::MustFollow(chunk_type, ChunkType::PLTE));
+                }
+            }
+            if let Some(first_idat) = first_idat {
+                let before_idat = chunk_type == ChunkType::PLTE
+                    || BEFORE_PLTE.contains(&chunk_type)
+                    || AFTER_PLTE.contains(&chunk_type)
+                    || BEFORE_IDAT.contains(&chunk_type);
+                if before_idat && index > first_idat {
+                    violations.push(SequenceViolation::MustPrecede(chunk_type, ChunkType::IDAT));
+                }
             }
         }
 
-        has_idat && has_iend // && has_ihdr // we deal ihdr in other part
+        violations
     }
 }
 
@@ -60,8 +386,120 @@ impl fmt::Display for RawPng {
             f,
             "Raw PNG Information:\nSignature: {:?}\nHeader: {}\nNumber of Chunks: {}",
             self.signature,
-            self.header.to_string(),
+            self.header,
             self.chunks.len()
-        )
+        )?;
+        if let Ok(Some(dimensions)) = self.physical_dimensions() {
+            write!(f, "\nPhysical Dimensions: {}", dimensions)?;
+        }
+        if !self.trailing_data.is_empty() {
+            write!(
+                f,
+                "\nTrailing Data: {} bytes after IEND",
+                self.trailing_data.len()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::PngReader;
+    use crate::writer::PngWriter;
+
+    /// A 2x2 indexed image with PLTE, tRNS and tEXt chunks.
+    fn sample_png() -> Vec<u8> {
+        let header = HeaderInfo::new(&[0, 0, 0, 2, 0, 0, 0, 2, 8, 3, 0, 0, 0]).unwrap();
+        let mut writer = PngWriter::new(header);
+        writer.chunks = vec![
+            Chunk::from_data(ChunkType::PLTE, vec![0, 0, 0, 255, 255, 255]),
+            Chunk::from_data(ChunkType::tRNS, vec![0]),
+            Chunk::from_data(ChunkType::tEXt, b"Title\0Sample".to_vec()),
+        ];
+        writer.encode(&[0, 1, 1, 0]).unwrap()
+    }
+
+    fn load(png: &[u8]) -> RawPng {
+        PngReader::from_bytes(png).load_png().unwrap()
+    }
+
+    #[test]
+    fn writes_unmodified_files_back_byte_for_byte() {
+        let mut png = sample_png();
+        // Corrupt the IEND CRC and add data after it; both must survive.
+        let crc_start = png.len() - 4;
+        png[crc_start] ^= 0xFF;
+        png.extend_from_slice(b"trailing");
+        assert_eq!(load(&png).to_bytes().unwrap(), png);
+    }
+
+    #[test]
+    fn recomputes_the_crc_of_edited_chunks() {
+        let mut raw_png = load(&sample_png());
+        let text = raw_png
+            .chunks
+            .iter_mut()
+            .find(|chunk| chunk.chunk_type == ChunkType::tEXt)
+            .unwrap();
+        text.set_data(b"Title\0Edited".to_vec());
+        let reloaded = load(&raw_png.to_bytes().unwrap());
+        let text = reloaded.find_chunk(ChunkType::tEXt).unwrap();
+        assert_eq!(text.data(), b"Title\0Edited");
+        assert!(text.verify_crc());
+    }
+
+    #[test]
+    fn rebuilds_an_edited_header() {
+        let mut raw_png = load(&sample_png());
+        raw_png.header.width = 3;
+        let reloaded = load(&raw_png.to_bytes().unwrap());
+        assert_eq!(reloaded.header.width, 3);
+        assert!(reloaded.header.chunk.verify_crc());
+
+        raw_png.header.width = 0;
+        assert!(matches!(
+            raw_png.to_bytes(),
+            Err(PngError::InvalidDimensions(0, 2))
+        ));
+    }
+
+    #[test]
+    fn rejects_chunks_out_of_order() {
+        let mut raw_png = load(&sample_png());
+        raw_png.chunks.swap(0, 1);
+        assert!(matches!(
+            raw_png.to_bytes(),
+            Err(PngError::InvalidChunkSequence(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_an_ihdr_of_the_wrong_length() {
+        let png = sample_png();
+        for length in [12, 14] {
+            let mut data = png[16..29].to_vec();
+            data.resize(length, 0);
+            let ihdr = Chunk::from_data(ChunkType::IHDR, data).to_bytes();
+            let png = [&png[..8], &ihdr, &png[33..]].concat();
+            assert!(matches!(
+                PngReader::from_bytes(&png).load_png(),
+                Err(PngError::InvalidChunkLength(ChunkType::IHDR, len)) if len == length
+            ));
+        }
+    }
+
+    #[test]
+    fn writes_the_data_length_of_each_chunk() {
+        let mut raw_png = load(&sample_png());
+        raw_png.chunks[0].length = 5;
+        raw_png
+            .chunks
+            .insert(2, Chunk::new(99, ChunkType::tEXt, b"a\0b".to_vec(), 0));
+        let reloaded = load(&raw_png.to_bytes().unwrap());
+        assert_eq!(reloaded.chunks[0].length, 6);
+        assert_eq!(reloaded.chunks[2].length, 3);
+        assert_eq!(reloaded.chunks[2].data(), b"a\0b");
     }
 }
@@ -0,0 +1,198 @@
+// row_decoder.rs
+use std::io;
+use std::io::prelude::*;
+
+use flate2::read::ZlibDecoder;
+
+use crate::chunk::{Chunk, ChunkType};
+use crate::chunk_iter::ChunkIter;
+use crate::error::{PngError, SequenceViolation};
+use crate::filter;
+use crate::header::{HeaderInfo, InterlaceMethod};
+use crate::palette::Palette;
+use crate::transparency::Transparency;
+
+/// The concatenated data of consecutive IDAT chunks, read straight from the
+/// chunk stream. Each chunk's CRC is checked once its data has been read.
+struct IdatStream<R> {
+    chunks: ChunkIter<R>,
+    /// Whether the chunk iterator is positioned inside an IDAT chunk.
+    in_idat: bool,
+    finished: bool,
+}
+
+impl<R: Read> IdatStream<R> {
+    fn next_idat(&mut self) -> Result<bool, PngError> {
+        match self.chunks.next_header() {
+            Some(Ok(header)) if header.chunk_type.is_same_as(&ChunkType::IDAT) => Ok(true),
+            Some(Ok(_)) => Ok(false),
+            Some(Err(err)) => Err(err),
+            None => Err(PngError::InvalidChunkSequence(vec![
+                SequenceViolation::MissingChunk(ChunkType::IEND),
+            ])),
+        }
+    }
+}
+
+impl<R: Read> Read for IdatStream<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        while !self.finished && !buffer.is_empty() {
+            if self.in_idat {
+                let read = self.chunks.read_data(buffer).map_err(io::Error::other)?;
+                if read > 0 {
+                    return Ok(read);
+                }
+                self.chunks.finish_data().map_err(io::Error::other)?;
+                self.in_idat = false;
+            }
+            self.in_idat = self.next_idat().map_err(io::Error::other)?;
+            self.finished = !self.in_idat;
+        }
+        Ok(0)
+    }
+}
+
+/// Decodes a non-interlaced PNG one scanline at a time, inflating IDAT data
+/// as it is needed. Only the current and previous rows are held in memory.
+pub struct RowDecoder<R> {
+    header: HeaderInfo,
+    palette: Option<Palette>,
+    transparency: Option<Transparency>,
+    /// Chunks between IHDR and the first IDAT.
+    chunks: Vec<Chunk>,
+    decoder: ZlibDecoder<IdatStream<R>>,
+    /// Grown as image data arrives, so a bogus IHDR width cannot force a huge
+    /// allocation up front.
+    filtered: Vec<u8>,
+    previous: Vec<u8>,
+    row: u32,
+}
+
+impl<R: Read> RowDecoder<R> {
+    /// Reads the chunks up to the first IDAT from `chunks`, which must not
+    /// have been advanced past its start.
+    pub fn new(mut chunks: ChunkIter<R>) -> Result<Self, PngError> {
+        let ihdr = chunks.next().unwrap_or(Err(PngError::TruncatedChunk(8)))?;
+        let header = HeaderInfo::from_chunk(ihdr)?;
+        if header.interlace_method == InterlaceMethod::Adam7 {
+            return Err(PngError::ParseError(
+                "Row-by-row decoding of interlaced images is not supported".to_string(),
+            ));
+        }
+
+        let mut before_idat = Vec::new();
+        loop {
+            let chunk_header = match chunks.next_header() {
+                Some(result) => result?,
+                None => {
+                    return Err(PngError::InvalidChunkSequence(vec![
+                        SequenceViolation::MissingChunk(ChunkType::IDAT),
+                    ]))
+                }
+            };
+            if chunk_header.chunk_type.is_same_as(&ChunkType::IDAT) {
+                break;
+            }
+            if chunk_header.chunk_type.is_same_as(&ChunkType::IEND) {
+                return Err(PngError::InvalidChunkSequence(vec![
+                    SequenceViolation::MissingChunk(ChunkType::IDAT),
+                ]));
+            }
+            let chunk = chunks.read_chunk()?;
+            chunk.validate()?;
+            before_idat.push(chunk);
+        }
+
+        let palette = before_idat
+            .iter()
+            .find(|chunk| chunk.chunk_type.is_same_as(&ChunkType::PLTE))
+            .map(|chunk| Palette::new(chunk.data()))
+            .transpose()?;
+        let transparency = before_idat
+            .iter()
+            .find(|chunk| chunk.chunk_type.is_same_as(&ChunkType::tRNS))
+            .map(|chunk| Transparency::new(chunk.data(), header.color_type))
+            .transpose()?;
+
+        Ok(Self {
+            header,
+            palette,
+            transparency,
+            chunks: before_idat,
+            decoder: ZlibDecoder::new(IdatStream {
+                chunks,
+                in_idat: true,
+                finished: false,
+            }),
+            filtered: Vec::new(),
+            previous: Vec::new(),
+            row: 0,
+        })
+    }
+
+    pub fn header(&self) -> &HeaderInfo {
+        &self.header
+    }
+
+    pub fn palette(&self) -> Option<&Palette> {
+        self.palette.as_ref()
+    }
+
+    pub fn transparency(&self) -> Option<&Transparency> {
+        self.transparency.as_ref()
+    }
+
+    /// Chunks found between IHDR and the first IDAT.
+    pub fn chunks(&self) -> &[Chunk] {
+        &self.chunks
+    }
+
+    /// Returns the next unfiltered scanline, without its filter type byte, or
+    /// `None` once every row has been read.
+    pub fn next_row(&mut self) -> Result<Option<&[u8]>, PngError> {
+        if self.row >= self.header.height {
+            return Ok(None);
+        }
+        let row_length = self.header.scanline_length() + 1;
+        self.filtered.clear();
+        let read = (&mut self.decoder)
+            .take(row_length as u64)
+            .read_to_end(&mut self.filtered)
+            .map_err(Self::unwrap_error)?;
+        if read < row_length {
+            return Err(PngError::ParseError(format!(
+                "Image data ends at row {} of {}",
+                self.row, self.header.height
+            )));
+        }
+        if self.row == 0 {
+            // The first row is unfiltered against an all-zero previous row.
+            self.previous = vec![0; row_length - 1];
+        }
+        self.previous = filter::unfilter_scanline(
+            self.filtered[0],
+            &self.filtered[1..],
+            &self.previous,
+            self.header.bytes_per_pixel(),
+        )
+        .map_err(|e| PngError::ParseError(e.to_string()))?;
+        self.row += 1;
+        Ok(Some(&self.previous))
+    }
+
+    /// Recovers a `PngError` raised while reading IDAT chunks; other errors
+    /// come from inflating the data.
+    fn unwrap_error(err: io::Error) -> PngError {
+        let kind = err.kind();
+        match err.into_inner().map(|inner| inner.downcast::<PngError>()) {
+            Some(Ok(err)) => *err,
+            Some(Err(inner))
+                if kind == io::ErrorKind::InvalidInput || kind == io::ErrorKind::InvalidData =>
+            {
+                PngError::ParseError(inner.to_string())
+            }
+            Some(Err(inner)) => PngError::Io(io::Error::new(kind, inner)),
+            None => PngError::Io(io::Error::from(kind)),
+        }
+    }
+}
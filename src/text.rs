@@ -0,0 +1,360 @@
+// text.rs
+use std::fmt;
+use std::io::prelude::*;
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::chunk::{Chunk, ChunkType};
+use crate::error::PngError;
+
+/// Text chunk types, in any of which a keyword may appear.
+pub const TEXT_CHUNKS: [ChunkType; 3] = [ChunkType::tEXt, ChunkType::zTXt, ChunkType::iTXt];
+
+/// Upper bound on decompressed text or ICC profile data, so a small zlib
+/// bomb cannot exhaust memory.
+pub const MAX_DECOMPRESSED_LENGTH: usize = 16 << 20;
+
+/// A keyword and text pair from a tEXt, zTXt or iTXt chunk.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TextChunk {
+    /// Uncompressed Latin-1 text (tEXt).
+    Text { keyword: String, text: String },
+    /// zlib-compressed Latin-1 text (zTXt).
+    Compressed { keyword: String, text: String },
+    /// UTF-8 text, optionally compressed, with a language tag and the keyword
+    /// translated into that language (iTXt).
+    International {
+        keyword: String,
+        compressed: bool,
+        language_tag: String,
+        translated_keyword: String,
+        text: String,
+    },
+}
+
+impl TextChunk {
+    pub fn from_chunk(chunk: &Chunk) -> Result<Self, PngError> {
+        let chunk_type = chunk.chunk_type;
+        let invalid = |reason: &str| PngError::InvalidTextChunk(chunk_type, reason.to_string());
+        let (keyword, rest) = split_null(chunk.data()).ok_or_else(|| invalid("missing keyword"))?;
+        // Keywords are only validated on write, so slightly malformed files
+        // still give up their text.
+        let keyword = latin1_to_string(keyword);
+        match chunk_type {
+            ChunkType::tEXt => Ok(TextChunk::Text {
+                keyword,
+                text: latin1_to_string(rest),
+            }),
+            ChunkType::zTXt => {
+                let (&method, compressed) = rest
+                    .split_first()
+                    .ok_or_else(|| invalid("missing compression method"))?;
+                if method != 0 {
+                    return Err(PngError::InvalidCompressionMethod(method));
+                }
+                let text = decompress(compressed, MAX_DECOMPRESSED_LENGTH).map_err(invalid)?;
+                Ok(TextChunk::Compressed {
+                    keyword,
+                    text: latin1_to_string(&text),
+                })
+            }
+            ChunkType::iTXt => {
+                if rest.len() < 2 {
+                    return Err(invalid("missing compression flag"));
+                }
+                let (flag, method) = (rest[0], rest[1]);
+                let (language_tag, rest) =
+                    split_null(&rest[2..]).ok_or_else(|| invalid("missing language tag"))?;
+                let (translated_keyword, text) =
+                    split_null(rest).ok_or_else(|| invalid("missing translated keyword"))?;
+                let text = match flag {
+                    0 => text.to_vec(),
+                    1 if method == 0 => {
+                        decompress(text, MAX_DECOMPRESSED_LENGTH).map_err(invalid)?
+                    }
+                    1 => return Err(PngError::InvalidCompressionMethod(method)),
+                    _ => return Err(invalid("invalid compression flag")),
+                };
+                let utf8 =
+                    |bytes: Vec<u8>| String::from_utf8(bytes).map_err(|_| invalid("not UTF-8"));
+                Ok(TextChunk::International {
+                    keyword,
+                    compressed: flag == 1,
+                    language_tag: utf8(language_tag.to_vec())?,
+                    translated_keyword: utf8(translated_keyword.to_vec())?,
+                    text: utf8(text)?,
+                })
+            }
+            _ => Err(invalid("not a text chunk")),
+        }
+    }
+
+    /// Encodes the entry, checking the keyword, that Latin-1 text is
+    /// representable, and that no field holds a null byte, which would
+    /// change how the entry reads back.
+    pub fn to_chunk(&self) -> Result<Chunk, PngError> {
+        validate_keyword(self.keyword())?;
+        let chunk_type = self.chunk_type();
+        let invalid = |reason: &str| PngError::InvalidTextChunk(chunk_type, reason.to_string());
+        let latin1 = |text: &str| {
+            if text.contains('\0') {
+                return Err(invalid("text contains a null byte"));
+            }
+            string_to_latin1(text).ok_or_else(|| invalid("text is not Latin-1"))
+        };
+        let mut data = latin1(self.keyword())?;
+        data.push(0);
+        match self {
+            TextChunk::Text { text, .. } => data.extend(latin1(text)?),
+            TextChunk::Compressed { text, .. } => {
+                data.push(0);
+                data.extend(compress(&latin1(text)?).map_err(PngError::Io)?);
+            }
+            TextChunk::International {
+                compressed,
+                language_tag,
+                translated_keyword,
+                text,
+                ..
+            } => {
+                // Language tags are RFC 3066 codes such as "en-GB".
+                if !language_tag
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-')
+                {
+                    return Err(invalid(
+                        "language tag is not ASCII letters, digits and hyphens",
+                    ));
+                }
+                if translated_keyword.contains('\0') {
+                    return Err(invalid("translated keyword contains a null byte"));
+                }
+                data.extend_from_slice(&[*compressed as u8, 0]);
+                data.extend_from_slice(language_tag.as_bytes());
+                data.push(0);
+                data.extend_from_slice(translated_keyword.as_bytes());
+                data.push(0);
+                if *compressed {
+                    data.extend(compress(text.as_bytes()).map_err(PngError::Io)?);
+                } else {
+                    data.extend_from_slice(text.as_bytes());
+                }
+            }
+        }
+        Ok(Chunk::from_data(chunk_type, data))
+    }
+
+    pub fn chunk_type(&self) -> ChunkType {
+        match self {
+            TextChunk::Text { .. } => ChunkType::tEXt,
+            TextChunk::Compressed { .. } => ChunkType::zTXt,
+            TextChunk::International { .. } => ChunkType::iTXt,
+        }
+    }
+
+    pub fn keyword(&self) -> &str {
+        match self {
+            TextChunk::Text { keyword, .. }
+            | TextChunk::Compressed { keyword, .. }
+            | TextChunk::International { keyword, .. } => keyword,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        match self {
+            TextChunk::Text { text, .. }
+            | TextChunk::Compressed { text, .. }
+            | TextChunk::International { text, .. } => text,
+        }
+    }
+}
+
+impl fmt::Display for TextChunk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TextChunk::International {
+                language_tag,
+                translated_keyword,
+                ..
+            } if !language_tag.is_empty() => write!(
+                f,
+                "{} ({}, {}): {}",
+                self.keyword(),
+                language_tag,
+                translated_keyword,
+                self.text()
+            ),
+            _ => write!(f, "{}: {}", self.keyword(), self.text()),
+        }
+    }
+}
+
+/// Keywords are 1 to 79 printable Latin-1 characters, with no leading,
+/// trailing or consecutive spaces.
+pub fn validate_keyword(keyword: &str) -> Result<(), PngError> {
+    let length = keyword.chars().count();
+    let printable = keyword
+        .chars()
+        .all(|c| matches!(c as u32, 32..=126 | 161..=255));
+    if !(1..=79).contains(&length)
+        || !printable
+        || keyword.starts_with(' ')
+        || keyword.ends_with(' ')
+        || keyword.contains("  ")
+    {
+        return Err(PngError::InvalidKeyword(keyword.to_string()));
+    }
+    Ok(())
+}
+
+/// Removes every text chunk with `keyword`, returning whether any were found.
+/// Keywords are compared as stored, so undecodable chunks can be removed too.
+pub fn remove_text(chunks: &mut Vec<Chunk>, keyword: &str) -> bool {
+    let Some(keyword) = string_to_latin1(keyword) else {
+        return false;
+    };
+    let count = chunks.len();
+    chunks.retain(|chunk| {
+        !TEXT_CHUNKS.contains(&chunk.chunk_type)
+            || split_null(chunk.data()).map(|(stored, _)| stored) != Some(&keyword[..])
+    });
+    chunks.len() != count
+}
+
+/// Splits `data` at its first null byte.
+pub fn split_null(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let position = data.iter().position(|&byte| byte == 0)?;
+    Some((&data[..position], &data[position + 1..]))
+}
+
+pub fn latin1_to_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|&byte| byte as char).collect()
+}
+
+fn string_to_latin1(text: &str) -> Option<Vec<u8>> {
+    text.chars().map(|c| u8::try_from(c as u32).ok()).collect()
+}
+
+/// Inflates zlib data, failing once the output would exceed `limit` bytes.
+pub fn decompress(data: &[u8], limit: usize) -> Result<Vec<u8>, &'static str> {
+    let mut decompressed = Vec::new();
+    ZlibDecoder::new(data)
+        .take(limit as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|_| "corrupt zlib data")?;
+    if decompressed.len() > limit {
+        return Err("decompressed data exceeds the size limit");
+    }
+    Ok(decompressed)
+}
+
+fn compress(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn international(language_tag: &str, translated_keyword: &str) -> TextChunk {
+        TextChunk::International {
+            keyword: "Title".to_string(),
+            compressed: false,
+            language_tag: language_tag.to_string(),
+            translated_keyword: translated_keyword.to_string(),
+            text: "Titre".to_string(),
+        }
+    }
+
+    #[test]
+    fn accepts_valid_keywords() {
+        for keyword in ["Title", "a", "Two words", "Café", &"k".repeat(79)] {
+            assert!(validate_keyword(keyword).is_ok(), "{:?}", keyword);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_keywords() {
+        for keyword in [
+            "",
+            &"k".repeat(80),
+            " Title",
+            "Title ",
+            "Two  spaces",
+            "Tab\tbed",
+            "New\nline",
+            "Delete\u{7F}",
+            "No\u{A0}break",
+            "Euro €",
+        ] {
+            assert!(
+                matches!(validate_keyword(keyword), Err(PngError::InvalidKeyword(_))),
+                "{:?}",
+                keyword
+            );
+        }
+    }
+
+    #[test]
+    fn round_trips_every_text_chunk_type() {
+        let entries = [
+            TextChunk::Text {
+                keyword: "Author".to_string(),
+                text: "Zoë".to_string(),
+            },
+            TextChunk::Compressed {
+                keyword: "Comment".to_string(),
+                text: "compressed ".repeat(50),
+            },
+            international("fr", "Titre"),
+            TextChunk::International {
+                keyword: "Description".to_string(),
+                compressed: true,
+                language_tag: "ja-JP".to_string(),
+                translated_keyword: "説明".to_string(),
+                text: "日本語のテキスト".to_string(),
+            },
+        ];
+        for entry in entries {
+            let chunk = entry.to_chunk().unwrap();
+            assert_eq!(chunk.chunk_type, entry.chunk_type());
+            assert_eq!(TextChunk::from_chunk(&chunk).unwrap(), entry);
+        }
+    }
+
+    #[test]
+    fn rejects_fields_that_would_not_read_back() {
+        let invalid = [
+            TextChunk::Text {
+                keyword: "Title".to_string(),
+                text: "null\0byte".to_string(),
+            },
+            TextChunk::Compressed {
+                keyword: "Title".to_string(),
+                text: "not Latin-1 €".to_string(),
+            },
+            international("en\0", "Title"),
+            international("fr-CA é", "Titre"),
+            international("fr", "Ti\0tre"),
+        ];
+        for entry in invalid {
+            assert!(
+                matches!(entry.to_chunk(), Err(PngError::InvalidTextChunk(..))),
+                "{:?}",
+                entry
+            );
+        }
+    }
+
+    #[test]
+    fn limits_decompressed_size() {
+        let compressed = compress(&[0; 1000]).unwrap();
+        assert_eq!(decompress(&compressed, 1000).unwrap().len(), 1000);
+        assert!(decompress(&compressed, 999).is_err());
+        assert!(decompress(b"not zlib", 1000).is_err());
+    }
+}
@@ -0,0 +1,189 @@
+// timestamp.rs
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::chunk::{Chunk, ChunkType};
+use crate::error::PngError;
+
+/// Last modification time from a tIME chunk, in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ModificationTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    /// Up to 60, to allow for leap seconds.
+    pub second: u8,
+}
+
+impl ModificationTime {
+    pub fn new(data: &[u8]) -> Result<ModificationTime, PngError> {
+        if data.len() != 7 {
+            return Err(PngError::InvalidChunkLength(ChunkType::tIME, data.len()));
+        }
+        ModificationTime::from_date_time(
+            u16::from_be_bytes([data[0], data[1]]),
+            data[2],
+            data[3],
+            data[4],
+            data[5],
+            data[6],
+        )
+    }
+
+    /// Checks that the values form a real date and time.
+    pub fn from_date_time(
+        year: u16,
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8,
+    ) -> Result<ModificationTime, PngError> {
+        let time = ModificationTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        };
+        if !(1..=12).contains(&month)
+            || day < 1
+            || day > days_in_month(year, month)
+            || hour > 23
+            || minute > 59
+            || second > 60
+        {
+            return Err(PngError::InvalidChunkData(
+                ChunkType::tIME,
+                format!("impossible date and time {}", time),
+            ));
+        }
+        Ok(time)
+    }
+
+    /// The current system time.
+    pub fn now() -> ModificationTime {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+        let seconds_of_day = seconds % 86400;
+        ModificationTime {
+            year: year as u16,
+            month,
+            day,
+            hour: (seconds_of_day / 3600) as u8,
+            minute: (seconds_of_day / 60 % 60) as u8,
+            second: (seconds_of_day % 60) as u8,
+        }
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = Vec::with_capacity(7);
+        data.extend_from_slice(&self.year.to_be_bytes());
+        data.extend_from_slice(&[self.month, self.day, self.hour, self.minute, self.second]);
+        Chunk::from_data(ChunkType::tIME, data)
+    }
+}
+
+impl fmt::Display for ModificationTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => {
+            29
+        }
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Converts days since 1970-01-01 to a (year, month, day) date in the
+/// proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    // Shift the epoch to 0000-03-01 so leap days fall at the end of a year.
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u8;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u8;
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_leap_days_and_leap_seconds() {
+        assert!(ModificationTime::from_date_time(2000, 2, 29, 0, 0, 0).is_ok());
+        assert!(ModificationTime::from_date_time(2024, 2, 29, 0, 0, 0).is_ok());
+        assert!(ModificationTime::from_date_time(2016, 12, 31, 23, 59, 60).is_ok());
+    }
+
+    #[test]
+    fn rejects_impossible_dates_and_times() {
+        for (year, month, day, hour, minute, second) in [
+            (1900, 2, 29, 0, 0, 0),
+            (2023, 2, 29, 0, 0, 0),
+            (2024, 0, 1, 0, 0, 0),
+            (2024, 13, 1, 0, 0, 0),
+            (2024, 1, 0, 0, 0, 0),
+            (2024, 4, 31, 0, 0, 0),
+            (2024, 1, 1, 24, 0, 0),
+            (2024, 1, 1, 0, 60, 0),
+            (2024, 1, 1, 0, 0, 61),
+        ] {
+            assert!(matches!(
+                ModificationTime::from_date_time(year, month, day, hour, minute, second),
+                Err(PngError::InvalidChunkData(ChunkType::tIME, _))
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_chunks_of_the_wrong_length() {
+        assert!(matches!(
+            ModificationTime::new(&[7, 232, 1, 1, 0, 0]),
+            Err(PngError::InvalidChunkLength(ChunkType::tIME, 6))
+        ));
+    }
+
+    #[test]
+    fn round_trips_through_a_chunk() {
+        let time = ModificationTime::from_date_time(2024, 2, 29, 13, 45, 60).unwrap();
+        let chunk = time.to_chunk();
+        assert_eq!(chunk.data(), [7, 232, 2, 29, 13, 45, 60]);
+        assert_eq!(ModificationTime::new(chunk.data()).unwrap(), time);
+        assert_eq!(time.to_string(), "2024-02-29 13:45:60 UTC");
+    }
+
+    #[test]
+    fn converts_days_to_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+    }
+}
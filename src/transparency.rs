@@ -0,0 +1,50 @@
+// transparency.rs
+use crate::error::PngError;
+use crate::header::ColorType;
+
+/// Contents of a tRNS chunk, whose layout depends on the image color type.
+#[derive(Debug, Clone)]
+pub enum Transparency {
+    /// Alpha for the leading palette entries; later entries are opaque.
+    Palette(Vec<u8>),
+    /// Gray sample value that is fully transparent.
+    Gray(u16),
+    /// RGB sample values that are fully transparent.
+    Rgb(u16, u16, u16),
+}
+
+impl Transparency {
+    pub fn new(data: &[u8], color_type: ColorType) -> Result<Transparency, PngError> {
+        let sample = |index: usize| u16::from_be_bytes([data[index * 2], data[index * 2 + 1]]);
+        match (color_type, data.len()) {
+            (ColorType::Grayscale, 2) => Ok(Transparency::Gray(sample(0))),
+            (ColorType::Rgb, 6) => Ok(Transparency::Rgb(sample(0), sample(1), sample(2))),
+            (ColorType::Indexed, len) if len <= 256 => Ok(Transparency::Palette(data.to_vec())),
+            (_, len) => Err(PngError::InvalidTransparency(color_type.as_u8(), len)),
+        }
+    }
+
+    /// Returns the alpha of a pixel given its raw samples, which are a single
+    /// palette index for indexed-color images.
+    pub fn alpha(&self, samples: &[u16]) -> u8 {
+        match self {
+            Transparency::Palette(alphas) => {
+                alphas.get(samples[0] as usize).copied().unwrap_or(255)
+            }
+            Transparency::Gray(gray) => {
+                if samples[0] == *gray {
+                    0
+                } else {
+                    255
+                }
+            }
+            Transparency::Rgb(r, g, b) => {
+                if samples[..3] == [*r, *g, *b] {
+                    0
+                } else {
+                    255
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,408 @@
+// writer.rs
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::chunk::{Chunk, ChunkType};
+use crate::chunk_iter::MAX_CHUNK_LENGTH;
+use crate::error::PngError;
+use crate::filter::{self, FilterStrategy};
+use crate::header::{HeaderInfo, InterlaceMethod};
+use crate::physical::PhysicalDimensions;
+use crate::png::ADAM7_PASSES;
+use crate::raw_data::{RawPng, SIGNATURE};
+use crate::text::{self, TextChunk};
+use crate::timestamp::ModificationTime;
+
+/// Default size of the data in each IDAT chunk.
+const DEFAULT_IDAT_SIZE: usize = 8192;
+
+/// Encodes unfiltered image data, laid out as `PngReader` decodes it: one
+/// packed scanline after another, without filter type bytes. Interlaced
+/// images take the same layout and are split into Adam7 passes on write.
+pub struct PngWriter {
+    pub header: HeaderInfo,
+    /// Chunks written between IHDR and the IDAT chunks, such as PLTE, tRNS
+    /// or text chunks, in the order given.
+    pub chunks: Vec<Chunk>,
+    /// How each scanline's filter type is chosen.
+    pub filter_strategy: FilterStrategy,
+    /// zlib compression level, from 0 (none) to 9 (best).
+    pub compression_level: u32,
+    /// Maximum number of bytes of compressed data per IDAT chunk.
+    pub idat_size: usize,
+}
+
+impl PngWriter {
+    /// Uses the minimum sum heuristic, except for indexed and sub-byte
+    /// images, which compress better unfiltered.
+    pub fn new(header: HeaderInfo) -> Self {
+        let filter_strategy = if header.color_type.is_indexed() || header.bit_depth.bits() < 8 {
+            FilterStrategy::Fixed(0)
+        } else {
+            FilterStrategy::MinimumSum
+        };
+        Self {
+            header,
+            chunks: Vec::new(),
+            filter_strategy,
+            compression_level: 6,
+            idat_size: DEFAULT_IDAT_SIZE,
+        }
+    }
+
+    /// Adds a text entry, replacing any entries with the same keyword.
+    pub fn set_text(&mut self, entry: &TextChunk) -> Result<(), PngError> {
+        let chunk = entry.to_chunk()?;
+        text::remove_text(&mut self.chunks, entry.keyword());
+        self.chunks.push(chunk);
+        Ok(())
+    }
+
+    /// Removes every text entry with `keyword`, returning whether any existed.
+    pub fn remove_text(&mut self, keyword: &str) -> bool {
+        text::remove_text(&mut self.chunks, keyword)
+    }
+
+    /// Sets the pHYs chunk, replacing any already added.
+    pub fn set_physical_dimensions(&mut self, dimensions: &PhysicalDimensions) {
+        self.chunks
+            .retain(|chunk| !chunk.chunk_type.is_same_as(&ChunkType::pHYs));
+        self.chunks.push(dimensions.to_chunk());
+    }
+
+    /// Sets the tIME chunk, replacing any already added. Pass
+    /// `ModificationTime::now()` to stamp the current time.
+    pub fn set_modification_time(&mut self, time: &ModificationTime) {
+        self.chunks
+            .retain(|chunk| !chunk.chunk_type.is_same_as(&ChunkType::tIME));
+        self.chunks.push(time.to_chunk());
+    }
+
+    pub fn save(&self, file_path: &str, image: &[u8]) -> Result<(), PngError> {
+        let file = File::create(file_path).map_err(PngError::Io)?;
+        let mut writer = BufWriter::new(file);
+        self.write(&mut writer, image)?;
+        writer.flush().map_err(PngError::Io)
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W, image: &[u8]) -> Result<(), PngError> {
+        let bytes = self.encode(image)?;
+        writer.write_all(&bytes).map_err(PngError::Io)
+    }
+
+    /// Encodes `image` into a complete PNG file. The header fields and the
+    /// chunks get the same checks as `RawPng::to_bytes`, so the output is
+    /// always readable by `PngReader`.
+    pub fn encode(&self, image: &[u8]) -> Result<Vec<u8>, PngError> {
+        let header = HeaderInfo::new(self.header.to_chunk().data())?;
+        if let FilterStrategy::Fixed(filter_type @ 5..) = self.filter_strategy {
+            return Err(PngError::InvalidFilterType(filter_type));
+        }
+        let expected = self.header.scanline_length() * self.header.height as usize;
+        if image.len() != expected {
+            return Err(PngError::InvalidImageDataLength(image.len(), expected));
+        }
+        let filtered = match self.header.interlace_method {
+            InterlaceMethod::None => self.filter_scanlines(image, self.header.width as usize)?,
+            InterlaceMethod::Adam7 => self.interlace_data(image)?,
+        };
+        let compressed =
+            Self::compress_data(&filtered, self.compression_level).map_err(PngError::Io)?;
+
+        let mut chunks = self.chunks.clone();
+        let idat_size = self.idat_size.clamp(1, MAX_CHUNK_LENGTH as usize);
+        for data in compressed.chunks(idat_size) {
+            chunks.push(Chunk::from_data(ChunkType::IDAT, data.to_vec()));
+        }
+        chunks.push(Chunk::from_data(ChunkType::IEND, Vec::new()));
+        RawPng::new(SIGNATURE, header, chunks)?.to_bytes()
+    }
+
+    fn compress_data(data: &[u8], level: u32) -> io::Result<Vec<u8>> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level.min(9)));
+        encoder.write_all(data)?;
+        encoder.finish()
+    }
+
+    /// Filters packed scanlines of `width` pixels, prefixing each with its
+    /// filter type byte.
+    fn filter_scanlines(&self, data: &[u8], width: usize) -> Result<Vec<u8>, PngError> {
+        let scanline_length = self.header.pass_scanline_length(width);
+        let mut filtered_data = Vec::with_capacity(data.len() + data.len() / scanline_length);
+        let mut previous_scanline = vec![0; scanline_length];
+        for scanline in data.chunks_exact(scanline_length) {
+            // The previous filtered scanline, used as context by BruteForce.
+            let context_start = filtered_data.len().saturating_sub(scanline_length + 1);
+            let (filter_type, filtered_scanline) = self.select_filter(
+                scanline,
+                &previous_scanline,
+                &filtered_data[context_start..],
+            )?;
+            filtered_data.push(filter_type);
+            filtered_data.extend_from_slice(&filtered_scanline);
+            previous_scanline.copy_from_slice(scanline);
+        }
+        Ok(filtered_data)
+    }
+
+    /// Picks a filter type for `scanline` according to the filter strategy
+    /// and returns it with the filtered bytes.
+    fn select_filter(
+        &self,
+        scanline: &[u8],
+        previous: &[u8],
+        context: &[u8],
+    ) -> Result<(u8, Vec<u8>), PngError> {
+        let bytes_per_pixel = self.header.bytes_per_pixel();
+        let filter = |filter_type| {
+            filter::filter_scanline(filter_type, scanline, previous, bytes_per_pixel)
+                .map_err(|_| PngError::InvalidFilterType(filter_type))
+        };
+        let filter_types = match self.filter_strategy {
+            FilterStrategy::Fixed(filter_type) => filter_type..=filter_type,
+            FilterStrategy::MinimumSum | FilterStrategy::Entropy | FilterStrategy::BruteForce => {
+                0..=4
+            }
+        };
+
+        let mut best: Option<(f64, u8, Vec<u8>)> = None;
+        for filter_type in filter_types {
+            let filtered = filter(filter_type)?;
+            let cost = match self.filter_strategy {
+                FilterStrategy::Fixed(_) => 0.0,
+                FilterStrategy::MinimumSum => filter::absolute_sum(&filtered) as f64,
+                FilterStrategy::Entropy => filter::entropy(&filtered),
+                FilterStrategy::BruteForce => {
+                    let mut candidate = context.to_vec();
+                    candidate.push(filter_type);
+                    candidate.extend_from_slice(&filtered);
+                    Self::compress_data(&candidate, self.compression_level)
+                        .map_err(PngError::Io)?
+                        .len() as f64
+                }
+            };
+            if best
+                .as_ref()
+                .is_none_or(|(best_cost, _, _)| cost < *best_cost)
+            {
+                best = Some((cost, filter_type, filtered));
+            }
+        }
+        let (_, filter_type, filtered) = best.expect("at least one filter type was tried");
+        Ok((filter_type, filtered))
+    }
+
+    /// Splits the image into its Adam7 passes and filters each of them.
+    fn interlace_data(&self, image: &[u8]) -> Result<Vec<u8>, PngError> {
+        let width = self.header.width as usize;
+        let height = self.header.height as usize;
+        let bits_per_pixel = self.header.bits_per_pixel();
+        let scanline_length = self.header.scanline_length();
+        let mut filtered_data = Vec::new();
+        for &(x_start, y_start, x_step, y_step) in ADAM7_PASSES.iter() {
+            let pass_width = width.saturating_sub(x_start).div_ceil(x_step);
+            let pass_height = height.saturating_sub(y_start).div_ceil(y_step);
+            if pass_width == 0 || pass_height == 0 {
+                continue;
+            }
+            let pass_scanline_length = self.header.pass_scanline_length(pass_width);
+            let mut pass = vec![0; pass_height * pass_scanline_length];
+            for (pass_y, pass_row) in pass.chunks_exact_mut(pass_scanline_length).enumerate() {
+                let row_start = (y_start + pass_y * y_step) * scanline_length;
+                let row = &image[row_start..row_start + scanline_length];
+                for pass_x in 0..pass_width {
+                    let x = x_start + pass_x * x_step;
+                    if bits_per_pixel >= 8 {
+                        let bytes = bits_per_pixel / 8;
+                        pass_row[pass_x * bytes..(pass_x + 1) * bytes]
+                            .copy_from_slice(&row[x * bytes..(x + 1) * bytes]);
+                    } else {
+                        let mask = (1u8 << bits_per_pixel) - 1;
+                        let bit_offset = x * bits_per_pixel;
+                        let value =
+                            (row[bit_offset / 8] >> (8 - bits_per_pixel - bit_offset % 8)) & mask;
+                        let pass_bit_offset = pass_x * bits_per_pixel;
+                        pass_row[pass_bit_offset / 8] |=
+                            value << (8 - bits_per_pixel - pass_bit_offset % 8);
+                    }
+                }
+            }
+            filtered_data.extend(self.filter_scanlines(&pass, pass_width)?);
+        }
+        Ok(filtered_data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::{BitDepth, ColorType};
+    use crate::png::PngReader;
+
+    const STRATEGIES: [FilterStrategy; 8] = [
+        FilterStrategy::Fixed(0),
+        FilterStrategy::Fixed(1),
+        FilterStrategy::Fixed(2),
+        FilterStrategy::Fixed(3),
+        FilterStrategy::Fixed(4),
+        FilterStrategy::MinimumSum,
+        FilterStrategy::Entropy,
+        FilterStrategy::BruteForce,
+    ];
+
+    fn make_header(
+        width: u32,
+        height: u32,
+        bit_depth: u8,
+        color_type: u8,
+        interlace: u8,
+    ) -> HeaderInfo {
+        let mut data = Vec::new();
+        data.extend_from_slice(&width.to_be_bytes());
+        data.extend_from_slice(&height.to_be_bytes());
+        data.extend_from_slice(&[bit_depth, color_type, 0, 0, interlace]);
+        HeaderInfo::new(&data).unwrap()
+    }
+
+    /// Pseudo-random scanlines with the padding bits of each row cleared, as
+    /// the decoder leaves them.
+    fn image(header: &HeaderInfo, seed: u32) -> Vec<u8> {
+        let scanline_length = header.scanline_length();
+        let used_bits = header.width as usize * header.bits_per_pixel();
+        let mut state = seed;
+        let mut image = Vec::new();
+        for _ in 0..header.height {
+            let mut row: Vec<u8> = (0..scanline_length)
+                .map(|_| {
+                    state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                    (state >> 16) as u8
+                })
+                .collect();
+            if !used_bits.is_multiple_of(8) {
+                row[scanline_length - 1] &= 0xFF << (8 - used_bits % 8);
+            }
+            image.extend(row);
+        }
+        image
+    }
+
+    fn decode(png: &[u8]) -> Vec<u8> {
+        let reader = PngReader::from_bytes(png);
+        let raw_png = PngReader::from_bytes(png).load_png().unwrap();
+        reader.image_data(&raw_png).unwrap()
+    }
+
+    #[test]
+    fn round_trips_every_format_and_filter_strategy() {
+        for color_type in [0, 2, 3, 4, 6] {
+            let allowed = ColorType::from_u8(color_type).unwrap().allowed_bit_depths();
+            for bit_depth in allowed.iter().map(|depth| depth.as_u8()) {
+                for interlace in [0, 1] {
+                    // 13x7 leaves partial Adam7 blocks; 1x1 leaves most passes empty.
+                    for (width, height) in [(13, 7), (1, 1)] {
+                        let header = make_header(width, height, bit_depth, color_type, interlace);
+                        let image = image(&header, width * 31 + bit_depth as u32);
+                        for strategy in STRATEGIES {
+                            let mut writer =
+                                PngWriter::new(HeaderInfo::new(header.to_chunk().data()).unwrap());
+                            if color_type == 3 {
+                                let entries = 1 << bit_depth;
+                                let palette = (0..entries * 3).map(|i| i as u8).collect();
+                                writer
+                                    .chunks
+                                    .push(Chunk::from_data(ChunkType::PLTE, palette));
+                            }
+                            writer.filter_strategy = strategy;
+                            writer.idat_size = 16;
+                            let png = writer.encode(&image).unwrap();
+                            assert_eq!(
+                                decode(&png),
+                                image,
+                                "color type {}, bit depth {}, interlace {}, {}x{}, {:?}",
+                                color_type,
+                                bit_depth,
+                                interlace,
+                                width,
+                                height,
+                                strategy
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_fixed_filter_type() {
+        let mut writer = PngWriter::new(make_header(2, 2, 8, 0, 0));
+        writer.filter_strategy = FilterStrategy::Fixed(5);
+        assert!(matches!(
+            writer.encode(&[0; 4]),
+            Err(PngError::InvalidFilterType(5))
+        ));
+    }
+
+    #[test]
+    fn rejects_image_data_of_the_wrong_length() {
+        let writer = PngWriter::new(make_header(2, 2, 8, 2, 0));
+        assert!(matches!(
+            writer.encode(&[0; 11]),
+            Err(PngError::InvalidImageDataLength(11, 12))
+        ));
+    }
+
+    #[test]
+    fn rejects_chunks_out_of_order() {
+        let mut writer = PngWriter::new(make_header(1, 1, 8, 3, 0));
+        writer
+            .chunks
+            .push(Chunk::from_data(ChunkType::tRNS, vec![0]));
+        writer
+            .chunks
+            .push(Chunk::from_data(ChunkType::PLTE, vec![0, 0, 0]));
+        assert!(matches!(
+            writer.encode(&[0]),
+            Err(PngError::InvalidChunkSequence(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_an_edited_header_the_reader_would_refuse() {
+        let mut writer = PngWriter::new(make_header(1, 1, 8, 2, 0));
+        writer.header.bit_depth = BitDepth::One;
+        assert!(matches!(
+            writer.encode(&[0]),
+            Err(PngError::InvalidBitDepth(2, 1))
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_palette_and_transparency_chunks() {
+        let mut writer = PngWriter::new(make_header(1, 1, 8, 6, 0));
+        writer
+            .chunks
+            .push(Chunk::from_data(ChunkType::tRNS, vec![0; 6]));
+        assert!(matches!(
+            writer.encode(&[0; 4]),
+            Err(PngError::InvalidTransparency(6, 6))
+        ));
+
+        let mut writer = PngWriter::new(make_header(1, 1, 8, 3, 0));
+        writer
+            .chunks
+            .push(Chunk::from_data(ChunkType::PLTE, vec![0, 0, 0]));
+        writer
+            .chunks
+            .push(Chunk::from_data(ChunkType::tRNS, vec![0, 0]));
+        assert!(matches!(
+            writer.encode(&[0]),
+            Err(PngError::InvalidTransparency(3, 2))
+        ));
+    }
+}